@@ -30,18 +30,33 @@ fn main() {
                 @end
         @end
 
-        @for k in map.keys()
-            - @k => @(map[k])
+        @for (i, [k, v]) in map
+            - @i: @k => @v
+        @end
+
+        @for x in [3, 1, 4, 1, 5, 9, 2, 6]
+            @if x == 9
+                @break
+            @elif x == 1
+                @continue
+            @end
+            - @x is item @(forloop.index + 1) of @(forloop.length)
+        @end
+
+        @for x in []
+            @x
+        @else
+            nothing to see here
         @end
 
         @for x in [1, 2, 3]
             @for y in [1, 2, 3]
-                @x * @y = @((x * y))
+                @x * @y = @(x * y)
             @end
         @end
     "#};
 
-    let output = flashmark::template::render(input);
+    let output = flashmark::template::render(input).unwrap_or_else(|err| format!("{err}"));
 
     println!("{}", output);
 }
@@ -0,0 +1,429 @@
+use std::collections::BTreeMap;
+
+use crate::slides::{Cards, Slide, SlideSplitter};
+
+/// Identifies one of the faces of a [`Card`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Face {
+    Front,
+    Back,
+    /// An extra face shown after the back, indexed from 0.
+    Extra(usize),
+}
+
+/// Information attached to a card that isn't part of any of its faces.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Metadata {
+    /// A stable identifier, used to keep track of a card across edits.
+    pub id: Option<String>,
+    pub title: Option<String>,
+    pub tags: Vec<String>,
+    /// The type of card, set with the `type` key.
+    pub kind: Option<String>,
+    /// Every other key.
+    pub fields: BTreeMap<String, String>,
+}
+
+/// A flashcard made out of consecutive slides.
+///
+/// The first slide is the front, the second slide is the back,
+/// and any remaining slides are extras.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Card<T = String> {
+    pub front: T,
+    pub back: Option<T>,
+    pub extra: Vec<T>,
+    pub metadata: Metadata,
+}
+
+/// An ordered collection of cards.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Deck<T = String> {
+    pub cards: Vec<Card<T>>,
+}
+
+impl Metadata {
+    /// Parse a metadata block made of `key: value` lines.
+    ///
+    /// Tags are separated by commas, and may be surrounded by square brackets.
+    /// Values may be surrounded by quotes, and lines without a colon are ignored.
+    ///
+    /// # Examples
+    /// ```
+    /// use flashmark::deck::Metadata;
+    ///
+    /// let metadata = Metadata::parse(r#"
+    ///     id: capital-of-france
+    ///     title: "Capital of France"
+    ///     tags: [geography, europe]
+    ///     type: basic
+    ///     difficulty: easy
+    /// "#);
+    ///
+    /// assert_eq!(metadata.id.as_deref(), Some("capital-of-france"));
+    /// assert_eq!(metadata.title.as_deref(), Some("Capital of France"));
+    /// assert_eq!(metadata.tags, ["geography", "europe"]);
+    /// assert_eq!(metadata.kind.as_deref(), Some("basic"));
+    /// assert_eq!(metadata.fields["difficulty"], "easy");
+    /// ```
+    pub fn parse(block: &str) -> Self {
+        let mut metadata = Self::default();
+
+        for line in block.lines() {
+            let Some((key, value)) = line.split_once(':') else {
+                continue;
+            };
+
+            let key = key.trim();
+            let value = unquote(value.trim());
+
+            match key {
+                "id" => metadata.id = Some(value.to_string()),
+                "title" => metadata.title = Some(value.to_string()),
+                "type" => metadata.kind = Some(value.to_string()),
+                "tags" => {
+                    let value = value
+                        .strip_prefix('[')
+                        .and_then(|value| value.strip_suffix(']'))
+                        .unwrap_or(value);
+
+                    metadata.tags.extend(
+                        value
+                            .split(',')
+                            .map(|tag| unquote(tag.trim()))
+                            .filter(|tag| !tag.is_empty())
+                            .map(str::to_string),
+                    );
+                }
+                _ => {
+                    metadata.fields.insert(key.to_string(), value.to_string());
+                }
+            }
+        }
+
+        metadata
+    }
+
+    /// Combine two metadata blocks.
+    ///
+    /// Values already set take priority, and tags are appended without duplicates.
+    pub fn merge(&mut self, other: Metadata) {
+        self.id = self.id.take().or(other.id);
+        self.title = self.title.take().or(other.title);
+        self.kind = self.kind.take().or(other.kind);
+
+        for tag in other.tags {
+            if !self.tags.contains(&tag) {
+                self.tags.push(tag);
+            }
+        }
+
+        for (key, value) in other.fields {
+            self.fields.entry(key).or_insert(value);
+        }
+    }
+}
+
+fn unquote(value: &str) -> &str {
+    ['"', '\'']
+        .into_iter()
+        .find_map(|quote| {
+            value
+                .strip_prefix(quote)
+                .and_then(|value| value.strip_suffix(quote))
+        })
+        .unwrap_or(value)
+}
+
+impl<T> Card<T> {
+    /// Create a card with only a front face.
+    pub fn new(front: T) -> Self {
+        Self {
+            front,
+            back: None,
+            extra: vec![],
+            metadata: Metadata::default(),
+        }
+    }
+
+    /// Group slides into a card.
+    ///
+    /// Returns `None` if there are no slides.
+    ///
+    /// # Examples
+    /// ```
+    /// use flashmark::deck::{Card, Face};
+    ///
+    /// let card = Card::from_slides(["front", "back", "hint"]).unwrap();
+    /// assert_eq!(card.face(Face::Front), Some(&"front"));
+    /// assert_eq!(card.face(Face::Back), Some(&"back"));
+    /// assert_eq!(card.face(Face::Extra(0)), Some(&"hint"));
+    /// assert_eq!(card.face(Face::Extra(1)), None);
+    /// ```
+    pub fn from_slides(slides: impl IntoIterator<Item = T>) -> Option<Self> {
+        let mut slides = slides.into_iter();
+
+        let mut card = Self::new(slides.next()?);
+        card.back = slides.next();
+        card.extra = slides.collect();
+
+        Some(card)
+    }
+
+    pub fn with_metadata(mut self, metadata: Metadata) -> Self {
+        self.metadata = metadata;
+        self
+    }
+
+    pub fn face(&self, face: Face) -> Option<&T> {
+        match face {
+            Face::Front => Some(&self.front),
+            Face::Back => self.back.as_ref(),
+            Face::Extra(index) => self.extra.get(index),
+        }
+    }
+
+    /// Returns every face of the card, in slide order.
+    pub fn faces(&self) -> impl Iterator<Item = (Face, &T)> {
+        std::iter::once((Face::Front, &self.front))
+            .chain(self.back.as_ref().map(|back| (Face::Back, back)))
+            .chain(
+                self.extra
+                    .iter()
+                    .enumerate()
+                    .map(|(index, extra)| (Face::Extra(index), extra)),
+            )
+    }
+
+    /// Returns the amount of faces (slides) in the card.
+    pub fn face_count(&self) -> usize {
+        1 + usize::from(self.back.is_some()) + self.extra.len()
+    }
+
+    /// Transform every face of the card, keeping its metadata.
+    pub fn map<U>(self, mut f: impl FnMut(T) -> U) -> Card<U> {
+        Card {
+            front: f(self.front),
+            back: self.back.map(&mut f),
+            extra: self.extra.into_iter().map(f).collect(),
+            metadata: self.metadata,
+        }
+    }
+}
+
+impl<'a> Card<&'a str> {
+    /// Group slides into a card,
+    /// taking the metadata blocks out of the slides and merging them in order.
+    ///
+    /// Returns `None` if there are no slides.
+    ///
+    /// # Examples
+    /// ```
+    /// use flashmark::deck::Card;
+    ///
+    /// let card = Card::from_slides_with_metadata([
+    ///     "+++\nid: one\ntags: a\n+++\nfront",
+    ///     "+++\ntags: b\n+++\nback",
+    /// ])
+    /// .unwrap();
+    ///
+    /// assert_eq!(card.front, "front");
+    /// assert_eq!(card.back, Some("back"));
+    /// assert_eq!(card.metadata.id.as_deref(), Some("one"));
+    /// assert_eq!(card.metadata.tags, ["a", "b"]);
+    /// ```
+    pub fn from_slides_with_metadata(slides: impl IntoIterator<Item = &'a str>) -> Option<Self> {
+        let mut metadata = Metadata::default();
+
+        let bodies = slides.into_iter().map(|slide| {
+            let slide = Slide::parse(slide);
+
+            if let Some(block) = slide.metadata {
+                metadata.merge(Metadata::parse(block));
+            }
+
+            slide.body
+        });
+
+        let card = Card::from_slides(bodies)?;
+
+        Some(card.with_metadata(metadata))
+    }
+}
+
+impl<'a> Deck<&'a str> {
+    /// Split a document into cards, taking the metadata blocks out of every slide.
+    ///
+    /// Cards made of a single blank slide are left out.
+    ///
+    /// # Examples
+    /// ```
+    /// use flashmark::{deck::Deck, slides::LineSplitter};
+    ///
+    /// let deck = Deck::split("a\n---\nb\n===\nc", LineSplitter::cards());
+    ///
+    /// assert_eq!(deck.len(), 2);
+    /// assert_eq!(deck.cards[0].back, Some("b"));
+    /// assert_eq!(deck.cards[1].front, "c");
+    /// ```
+    pub fn split(document: &'a str, splitter: impl SlideSplitter) -> Self {
+        Cards::new(document, splitter)
+            .filter(|slides| !matches!(slides.as_slice(), [slide] if slide.trim().is_empty()))
+            .filter_map(Card::from_slides_with_metadata)
+            .collect()
+    }
+}
+
+impl<T> Deck<T> {
+    pub fn new() -> Self {
+        Self { cards: vec![] }
+    }
+
+    pub fn len(&self) -> usize {
+        self.cards.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.cards.is_empty()
+    }
+
+    pub fn iter(&self) -> std::slice::Iter<'_, Card<T>> {
+        self.cards.iter()
+    }
+
+    /// Returns every face of every card, in order.
+    pub fn slides(&self) -> impl Iterator<Item = &T> {
+        self.cards
+            .iter()
+            .flat_map(|card| card.faces().map(|(_, slide)| slide))
+    }
+
+    /// Transform every face of every card in the deck.
+    pub fn map<U>(self, mut f: impl FnMut(T) -> U) -> Deck<U> {
+        self.cards
+            .into_iter()
+            .map(|card| card.map(&mut f))
+            .collect()
+    }
+}
+
+impl<T> Default for Deck<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T> FromIterator<Card<T>> for Deck<T> {
+    fn from_iter<I: IntoIterator<Item = Card<T>>>(iter: I) -> Self {
+        Self {
+            cards: iter.into_iter().collect(),
+        }
+    }
+}
+
+impl<T> IntoIterator for Deck<T> {
+    type Item = Card<T>;
+    type IntoIter = std::vec::IntoIter<Card<T>>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.cards.into_iter()
+    }
+}
+
+impl<'a, T> IntoIterator for &'a Deck<T> {
+    type Item = &'a Card<T>;
+    type IntoIter = std::slice::Iter<'a, Card<T>>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.cards.iter()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn no_slides() {
+        assert_eq!(Card::<&str>::from_slides([]), None);
+    }
+
+    #[test]
+    fn front_only() {
+        let card = Card::from_slides(["front"]).unwrap();
+        assert_eq!(card.back, None);
+        assert!(card.extra.is_empty());
+        assert_eq!(card.face_count(), 1);
+    }
+
+    #[test]
+    fn faces_in_order() {
+        let card = Card::from_slides(["a", "b", "c", "d"]).unwrap();
+        let faces: Vec<_> = card.faces().collect();
+
+        assert_eq!(
+            faces,
+            [
+                (Face::Front, &"a"),
+                (Face::Back, &"b"),
+                (Face::Extra(0), &"c"),
+                (Face::Extra(1), &"d"),
+            ]
+        );
+    }
+
+    #[test]
+    fn map_keeps_metadata() {
+        let metadata = Metadata {
+            id: Some("card".into()),
+            ..Metadata::default()
+        };
+
+        let card = Card::from_slides(["a", "b"])
+            .unwrap()
+            .with_metadata(metadata.clone())
+            .map(str::to_uppercase);
+
+        assert_eq!(card.front, "A");
+        assert_eq!(card.back.as_deref(), Some("B"));
+        assert_eq!(card.metadata, metadata);
+    }
+
+    #[test]
+    fn metadata_ignores_invalid_lines() {
+        let metadata = Metadata::parse("not a field\n\nid: one\ntags:\n");
+        assert_eq!(
+            metadata,
+            Metadata {
+                id: Some("one".into()),
+                ..Metadata::default()
+            }
+        );
+    }
+
+    #[test]
+    fn metadata_merge_priority() {
+        let mut metadata = Metadata::parse("id: one\ntags: a, b\nsource: book");
+        metadata.merge(Metadata::parse(
+            "id: two\ntitle: Two\ntags: b, c\nsource: web",
+        ));
+
+        assert_eq!(metadata.id.as_deref(), Some("one"));
+        assert_eq!(metadata.title.as_deref(), Some("Two"));
+        assert_eq!(metadata.tags, ["a", "b", "c"]);
+        assert_eq!(metadata.fields["source"], "book");
+    }
+
+    #[test]
+    fn deck_slides() {
+        let deck: Deck<_> = [
+            Card::from_slides(["a", "b"]).unwrap(),
+            Card::from_slides(["c"]).unwrap(),
+        ]
+        .into_iter()
+        .collect();
+
+        let slides: Vec<_> = deck.slides().copied().collect();
+        assert_eq!(slides, ["a", "b", "c"]);
+    }
+}
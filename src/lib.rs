@@ -1,18 +1,41 @@
+pub mod deck;
 pub mod markdown;
 pub mod math;
 pub mod parsing;
+pub mod renderer;
 pub mod slides;
 pub mod template;
 
-pub fn render(input: &str) -> Vec<String> {
-    use markdown_it::MarkdownIt;
+pub use deck::{Card, Deck};
+pub use renderer::{Renderer, RendererBuilder};
+pub use slides::{LineSplitter, SlideSplitter};
+pub use template::{Diagnostic, Error};
 
-    let mut md = MarkdownIt::new();
+/// Render a document into a deck of cards with HTML faces.
+///
+/// Slides are separated by `---`, and the whole document is a single card.
+///
+/// Fails if anything in the template failed to parse or evaluate.
+///
+/// This creates a new [`Renderer`] every time,
+/// prefer creating one and reusing it when rendering many documents.
+pub fn render(input: &str) -> Result<Deck, Error> {
+    Renderer::new().render(input)
+}
 
-    markdown_it::plugins::cmark::add(&mut md);
-    markdown::math::add(&mut md);
+/// Render a document into a deck of cards,
+/// using `splitter` to decide where slides and cards are separated.
+///
+/// The `@slide` and `@card` template directives write the splitter's separators.
+pub fn render_with_splitter(
+    input: &str,
+    splitter: impl SlideSplitter + Send + Sync + 'static,
+) -> Result<Deck, Error> {
+    Renderer::builder().splitter(splitter).build().render(input)
+}
 
-    slides::Slides::new(&template::render(input))
-        .map(|slide| md.parse(slide).render())
-        .collect()
+/// Render as much of a document as possible,
+/// returning every diagnostic reported by the template alongside the deck.
+pub fn render_lenient(input: &str) -> (Deck, Vec<Diagnostic>) {
+    Renderer::new().render_lenient(input)
 }
@@ -16,7 +16,7 @@ impl<'a> Tokenizer<'a> {
         }
     }
 
-    const SIMPLE_SYMBOL_MAPPING: &[(&'static str, token::SimpleSymbol)] = &[
+    const SIMPLE_SYMBOL_MAPPING: &'static [(&'static str, token::SimpleSymbol)] = &[
         ("+", SimpleSymbol::Plus),
         ("-", SimpleSymbol::Minus),
         ("*", SimpleSymbol::DotProduct),
@@ -36,19 +36,19 @@ impl<'a> Tokenizer<'a> {
         ("int", SimpleSymbol::Integral),
     ];
 
-    const SPECIAL_SYMBOL_MAPPING: &[(&'static str, SpecialSymbol)] = &[
+    const SPECIAL_SYMBOL_MAPPING: &'static [(&'static str, SpecialSymbol)] = &[
         ("/", SpecialSymbol::Slash),
         ("^", SpecialSymbol::Caret),
         ("_", SpecialSymbol::Underscore),
     ];
 
-    const GROUPING_MAPPING: &[(&'static str, &'static str, GroupingKind)] = &[
+    const GROUPING_MAPPING: &'static [(&'static str, &'static str, GroupingKind)] = &[
         ("(", ")", GroupingKind::Paren),
         ("[", "]", GroupingKind::Bracket),
         ("{", "}", GroupingKind::Brace),
     ];
 
-    const FUNCTION_MAPPING: &[(&'static str, Function)] = &[
+    const FUNCTION_MAPPING: &'static [(&'static str, Function)] = &[
         ("sqrt", Function::Sqrt),
         ("sin", Function::Sin),
         ("cos", Function::Cos),
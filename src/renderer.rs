@@ -0,0 +1,251 @@
+use std::sync::Arc;
+
+use markdown_it::MarkdownIt;
+
+use crate::{
+    slides::{LineSplitter, Separators, SlideSplitter},
+    template::{self, CompiledTemplate, Environment, Formatter, Limits, TemplateLoader},
+    Deck, Diagnostic, Error,
+};
+
+/// Renders documents into decks, reusing the same markdown parser and rhai engine.
+///
+/// Creating a renderer is expensive, so it should be created once
+/// and used to render every document.
+/// With the `sync` feature enabled, a renderer can be shared between threads.
+///
+/// # Examples
+/// ```
+/// use flashmark::Renderer;
+///
+/// let renderer = Renderer::new();
+///
+/// for name in ["World", "Flashmark"] {
+///     let deck = renderer.render(&format!("Hello, {name}!")).unwrap();
+///     assert_eq!(deck.cards[0].front, format!("<p>Hello, {name}!</p>\n"));
+/// }
+/// ```
+pub struct Renderer {
+    md: MarkdownIt,
+    engine: rhai::Shared<rhai::Engine>,
+    splitter: Box<dyn SlideSplitter + Send + Sync>,
+    separators: Separators,
+    seed: Option<u64>,
+    loader: Option<Arc<dyn TemplateLoader + Send + Sync>>,
+    limits: Limits,
+    autoescape: bool,
+    formatter: Arc<Formatter>,
+}
+
+/// Configures a [`Renderer`].
+pub struct RendererBuilder {
+    md: Option<MarkdownIt>,
+    engine: Option<rhai::Engine>,
+    splitter: Box<dyn SlideSplitter + Send + Sync>,
+    seed: Option<u64>,
+    loader: Option<Arc<dyn TemplateLoader + Send + Sync>>,
+    limits: Limits,
+    autoescape: bool,
+    formatter: Arc<Formatter>,
+}
+
+impl Renderer {
+    /// Create a renderer with the default configuration.
+    pub fn new() -> Self {
+        Self::builder().build()
+    }
+
+    /// Create a renderer for decks that aren't trusted,
+    /// using [`template::new_safe_engine`].
+    pub fn safe() -> Self {
+        Self::builder().engine(template::new_safe_engine()).build()
+    }
+
+    pub fn builder() -> RendererBuilder {
+        RendererBuilder {
+            md: None,
+            engine: None,
+            splitter: Box::new(LineSplitter::default()),
+            seed: None,
+            loader: None,
+            limits: Limits::default(),
+            autoescape: true,
+            formatter: Arc::default(),
+        }
+    }
+
+    /// Render a document into a deck of cards with HTML faces.
+    ///
+    /// Fails if anything in the template failed to parse or evaluate.
+    pub fn render(&self, input: &str) -> Result<Deck, Error> {
+        self.render_template(input)
+            .into_result()
+            .map(|markdown| self.render_markdown(&markdown))
+    }
+
+    /// Render as much of a document as possible,
+    /// returning every diagnostic reported by the template alongside the deck.
+    pub fn render_lenient(&self, input: &str) -> (Deck, Vec<Diagnostic>) {
+        let output = self.render_template(input);
+
+        (self.render_markdown(&output.text), output.diagnostics)
+    }
+
+    /// Render the template of a document into markdown.
+    pub fn render_template(&self, input: &str) -> template::Output {
+        template::render_document(self.environment(), input)
+    }
+
+    /// Parse a document once, so it can be rendered many times with [`Renderer::render_compiled`].
+    pub fn compile(&self, input: &str) -> Result<CompiledTemplate, Error> {
+        CompiledTemplate::compile_in(&self.environment(), input)
+    }
+
+    /// Render a compiled document into a deck, with some variables in scope.
+    pub fn render_compiled(
+        &self,
+        template: &CompiledTemplate,
+        scope: rhai::Scope<'static>,
+    ) -> Result<Deck, Error> {
+        let mut env = self.environment();
+        *env.scope_mut() = scope;
+
+        template
+            .render_lenient(env)
+            .into_result()
+            .map(|markdown| self.render_markdown(&markdown))
+    }
+
+    /// Split rendered markdown into cards, and render each face into HTML.
+    pub fn render_markdown(&self, markdown: &str) -> Deck {
+        Deck::split(markdown, &self.splitter).map(|slide| self.md.parse(slide).render())
+    }
+
+    /// Create a fresh environment using the renderer's engine, separators, limits,
+    /// escaping, formatter, seed and loader.
+    pub fn environment(&self) -> Environment {
+        let mut env = Environment::with_engine(self.engine.clone());
+        env.set_separators(self.separators.clone());
+        env.set_limits(self.limits);
+        env.set_autoescape(self.autoescape);
+        env.set_formatter(self.formatter.clone());
+
+        if let Some(loader) = &self.loader {
+            env.set_loader(loader.clone());
+        }
+
+        if let Some(seed) = self.seed {
+            env.set_seed(seed);
+        }
+
+        env
+    }
+
+    pub fn engine(&self) -> &rhai::Engine {
+        &self.engine
+    }
+
+    pub fn markdown(&self) -> &MarkdownIt {
+        &self.md
+    }
+}
+
+impl Default for Renderer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl RendererBuilder {
+    /// Use a custom markdown parser.
+    ///
+    /// Defaults to CommonMark with the math plugin.
+    pub fn markdown(mut self, md: MarkdownIt) -> Self {
+        self.md = Some(md);
+        self
+    }
+
+    /// Use a custom rhai engine.
+    ///
+    /// Defaults to [`template::new_engine`].
+    pub fn engine(mut self, engine: rhai::Engine) -> Self {
+        self.engine = Some(engine);
+        self
+    }
+
+    /// Decide where slides and cards are separated.
+    ///
+    /// Defaults to [`LineSplitter::default`], which never separates cards,
+    /// so decks using `@card` need a splitter like [`LineSplitter::cards`].
+    pub fn splitter(mut self, splitter: impl SlideSplitter + Send + Sync + 'static) -> Self {
+        self.splitter = Box::new(splitter);
+        self
+    }
+
+    /// Seed the `rand` module, so rendering a document always gives the same deck.
+    ///
+    /// To give each card different numbers within a review session,
+    /// derive the seed from the card with [`template::random::derive_seed`].
+    pub fn seed(mut self, seed: u64) -> Self {
+        self.seed = Some(seed);
+        self
+    }
+
+    /// Load the templates included with `@include`.
+    ///
+    /// By default, nothing can be included.
+    pub fn loader(mut self, loader: impl TemplateLoader + Send + Sync + 'static) -> Self {
+        self.loader = Some(Arc::new(loader));
+        self
+    }
+
+    /// Limit how much templates can render.
+    ///
+    /// Defaults to [`Limits::default`].
+    pub fn limits(mut self, limits: Limits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    /// Choose whether interpolated values are escaped.
+    ///
+    /// Defaults to `true`, see [`Environment::set_autoescape`].
+    pub fn autoescape(mut self, autoescape: bool) -> Self {
+        self.autoescape = autoescape;
+        self
+    }
+
+    /// Choose how interpolated values are written.
+    ///
+    /// Defaults to [`Formatter::new`].
+    pub fn formatter(mut self, formatter: Formatter) -> Self {
+        self.formatter = Arc::new(formatter);
+        self
+    }
+
+    pub fn build(self) -> Renderer {
+        let md = self.md.unwrap_or_else(|| {
+            let mut md = MarkdownIt::new();
+
+            markdown_it::plugins::cmark::add(&mut md);
+            crate::markdown::math::add(&mut md);
+
+            md
+        });
+
+        let engine = self.engine.unwrap_or_else(template::new_engine);
+        let separators = Separators::of(&self.splitter);
+
+        Renderer {
+            md,
+            engine: engine.into(),
+            splitter: self.splitter,
+            separators,
+            seed: self.seed,
+            loader: self.loader,
+            limits: self.limits,
+            autoescape: self.autoescape,
+            formatter: self.formatter,
+        }
+    }
+}
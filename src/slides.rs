@@ -1,15 +1,509 @@
-/// Given a string, return slices of the string separated by a line break,
-/// followed by a horizontal rule (---), followed by another line break.
-pub struct Slides<'a> {
+use std::fmt;
+
+/// The kind of boundary marked by a separator line.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Boundary {
+    /// Separates two slides of the same card.
+    Slide,
+    /// Separates two cards.
+    Card,
+}
+
+/// Decides which lines separate slides and cards.
+pub trait SlideSplitter {
+    /// Returns the boundary marked by a line, or `None` if it isn't a separator.
+    ///
+    /// The line doesn't include its line break.
+    fn classify(&self, line: &str) -> Option<Boundary>;
+
+    /// Returns a line that marks the boundary,
+    /// used when templates generate their own separators.
+    ///
+    /// Returns `None` if there's no canonical separator for the boundary.
+    fn separator(&self, boundary: Boundary) -> Option<&str> {
+        let _ = boundary;
+        None
+    }
+}
+
+/// The lines to write when generating slide and card boundaries.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Separators {
+    pub slide: Option<String>,
+    pub card: Option<String>,
+}
+
+/// Splits on lines that are exactly equal to one of the separators.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LineSplitter {
+    slide: String,
+    card: Option<String>,
+}
+
+/// Splits using a function, see [`from_fn`].
+#[derive(Clone, Copy)]
+pub struct FnSplitter<F>(F);
+
+/// Create a splitter out of a function classifying lines.
+///
+/// # Examples
+/// ```
+/// use flashmark::slides::{self, Boundary, Slides};
+///
+/// // any line of at least three `%` separates slides
+/// let splitter = slides::from_fn(|line| {
+///     (line.len() >= 3 && line.chars().all(|ch| ch == '%')).then_some(Boundary::Slide)
+/// });
+///
+/// let slides: Vec<_> = Slides::with_splitter("a\n%%%%%\nb", splitter).collect();
+/// assert_eq!(slides, ["a", "b"]);
+/// ```
+pub fn from_fn<F: Fn(&str) -> Option<Boundary>>(f: F) -> FnSplitter<F> {
+    FnSplitter(f)
+}
+
+impl fmt::Display for Boundary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Boundary::Slide => write!(f, "slide"),
+            Boundary::Card => write!(f, "card"),
+        }
+    }
+}
+
+impl Separators {
+    /// Returns the separators used by a splitter.
+    pub fn of(splitter: &impl SlideSplitter) -> Self {
+        Self {
+            slide: splitter.separator(Boundary::Slide).map(str::to_string),
+            card: splitter.separator(Boundary::Card).map(str::to_string),
+        }
+    }
+
+    pub fn get(&self, boundary: Boundary) -> Option<&str> {
+        match boundary {
+            Boundary::Slide => self.slide.as_deref(),
+            Boundary::Card => self.card.as_deref(),
+        }
+    }
+}
+
+impl Default for Separators {
+    /// The separators of the default [`LineSplitter`].
+    fn default() -> Self {
+        Self::of(&LineSplitter::default())
+    }
+}
+
+impl LineSplitter {
+    /// Create a splitter that only separates slides, never cards.
+    pub fn new(slide: impl Into<String>) -> Self {
+        Self {
+            slide: slide.into(),
+            card: None,
+        }
+    }
+
+    /// Also separate cards on lines equal to `card`.
+    pub fn with_card_separator(mut self, card: impl Into<String>) -> Self {
+        self.card = Some(card.into());
+        self
+    }
+
+    /// Separates slides with `---` and cards with `===`.
+    pub fn cards() -> Self {
+        Self::new("---").with_card_separator("===")
+    }
+
+    pub fn slide_separator(&self) -> &str {
+        &self.slide
+    }
+
+    pub fn card_separator(&self) -> Option<&str> {
+        self.card.as_deref()
+    }
+}
+
+impl Default for LineSplitter {
+    /// Separates slides with `---`, and never separates cards.
+    fn default() -> Self {
+        Self::new("---")
+    }
+}
+
+impl SlideSplitter for LineSplitter {
+    fn classify(&self, line: &str) -> Option<Boundary> {
+        if line == self.slide {
+            Some(Boundary::Slide)
+        } else if self.card.as_deref() == Some(line) {
+            Some(Boundary::Card)
+        } else {
+            None
+        }
+    }
+
+    fn separator(&self, boundary: Boundary) -> Option<&str> {
+        match boundary {
+            Boundary::Slide => Some(&self.slide),
+            Boundary::Card => self.card.as_deref(),
+        }
+    }
+}
+
+impl<F: Fn(&str) -> Option<Boundary>> SlideSplitter for FnSplitter<F> {
+    fn classify(&self, line: &str) -> Option<Boundary> {
+        (self.0)(line)
+    }
+}
+
+impl<S: SlideSplitter + ?Sized> SlideSplitter for &S {
+    fn classify(&self, line: &str) -> Option<Boundary> {
+        (**self).classify(line)
+    }
+
+    fn separator(&self, boundary: Boundary) -> Option<&str> {
+        (**self).separator(boundary)
+    }
+}
+
+impl<S: SlideSplitter + ?Sized> SlideSplitter for Box<S> {
+    fn classify(&self, line: &str) -> Option<Boundary> {
+        (**self).classify(line)
+    }
+
+    fn separator(&self, boundary: Boundary) -> Option<&str> {
+        (**self).separator(boundary)
+    }
+}
+
+/// Given a string, return slices of the string separated by separator lines.
+///
+/// By default, slides are separated by lines of `---`.
+///
+/// Separators inside of fenced code blocks and raw HTML blocks are ignored.
+pub struct Slides<'a, S = LineSplitter> {
     string: &'a str,
+    splitter: S,
+}
+
+/// Given a string, return the slides of each card.
+pub struct Cards<'a, S = LineSplitter> {
+    slides: Slides<'a, S>,
 }
 
 impl<'a> Slides<'a> {
     pub fn new(string: &'a str) -> Self {
-        Self { string }
+        Self::with_splitter(string, LineSplitter::default())
+    }
+}
+
+impl<'a, S: SlideSplitter> Slides<'a, S> {
+    pub fn with_splitter(string: &'a str, splitter: S) -> Self {
+        Self { string, splitter }
+    }
+
+    /// Returns the next slide,
+    /// along with the boundary that ended it (or `None` if it's the last slide).
+    pub fn next_with_boundary(&mut self) -> Option<(&'a str, Option<Boundary>)> {
+        if self.string.is_empty() {
+            return None;
+        }
+
+        let mut blocks = BlockTracker::default();
+
+        let mut end = 0;
+        for line in self.string.split_inclusive('\n') {
+            let start = end;
+            end += line.len();
+
+            let text = strip_suffix_newline(line).unwrap_or(line);
+
+            if blocks.is_literal(text) {
+                continue;
+            }
+
+            if let Some(boundary) = self.splitter.classify(text) {
+                let slide = &self.string[..start];
+                let slide = strip_suffix_newline(slide).unwrap_or(slide);
+
+                self.string = &self.string[end..];
+                return Some((slide, Some(boundary)));
+            }
+        }
+
+        Some((std::mem::take(&mut self.string), None))
+    }
+}
+
+impl<'a, S: SlideSplitter> Cards<'a, S> {
+    pub fn new(string: &'a str, splitter: S) -> Self {
+        Self {
+            slides: Slides::with_splitter(string, splitter),
+        }
     }
 }
 
+/// A slide split into its metadata block and its body.
+///
+/// The metadata block is optional, and has to be at the very start of the slide,
+/// surrounded by lines of `+++`:
+/// ```text
+/// +++
+/// id: capital-of-france
+/// tags: geography, europe
+/// +++
+/// What is the capital of France?
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Slide<'a> {
+    pub metadata: Option<&'a str>,
+    pub body: &'a str,
+}
+
+impl<'a> Slide<'a> {
+    /// Split the metadata block out of a slide.
+    ///
+    /// # Examples
+    /// ```
+    /// use flashmark::slides::Slide;
+    ///
+    /// let slide = Slide::parse("+++\nid: one\n+++\nHello, world!");
+    /// assert_eq!(slide.metadata, Some("id: one\n"));
+    /// assert_eq!(slide.body, "Hello, world!");
+    ///
+    /// let slide = Slide::parse("Hello, world!");
+    /// assert_eq!(slide.metadata, None);
+    /// assert_eq!(slide.body, "Hello, world!");
+    /// ```
+    pub fn parse(slide: &'a str) -> Self {
+        let split = strip_fence_line(slide).and_then(|stripped| {
+            let mut offset = 0;
+            stripped.split_inclusive('\n').find_map(|line| {
+                let start = offset;
+                offset += line.len();
+
+                strip_fence_line(line)
+                    .filter(|rest| rest.is_empty())
+                    .map(|_| (&stripped[..start], &stripped[offset..]))
+            })
+        });
+
+        match split {
+            Some((metadata, body)) => Slide {
+                metadata: Some(metadata),
+                body,
+            },
+            None => Slide {
+                metadata: None,
+                body: slide,
+            },
+        }
+    }
+}
+
+/// Strips a line of `+++` from the start of the input.
+fn strip_fence_line(input: &str) -> Option<&str> {
+    let rest = input.strip_prefix("+++")?;
+    empty_or_else(rest, || strip_prefix_newline(rest))
+}
+
+/// Keeps track of the blocks whose contents are taken literally by markdown,
+/// where separator lines shouldn't split slides.
+#[derive(Debug, Default)]
+pub(crate) struct BlockTracker {
+    open: Option<LiteralBlock>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum LiteralBlock {
+    /// A fenced code block, opened by at least three of the same character.
+    Fence { marker: char, len: usize },
+    /// A raw HTML block, closed by a line containing the terminator.
+    Html { terminator: &'static str },
+    /// A raw HTML block, closed by a blank line.
+    HtmlParagraph,
+}
+
+/// Raw HTML blocks whose contents can contain blank lines,
+/// and the terminator that closes them.
+const HTML_BLOCKS: &[(&str, &str)] = &[
+    ("<script", "</script>"),
+    ("<pre", "</pre>"),
+    ("<style", "</style>"),
+    ("<textarea", "</textarea>"),
+    ("<!--", "-->"),
+    ("<?", "?>"),
+    ("<![CDATA[", "]]>"),
+    ("<!", ">"),
+];
+
+/// Tags that start a raw HTML block that continues until the next blank line.
+const HTML_BLOCK_TAGS: &[&str] = &[
+    "address",
+    "article",
+    "aside",
+    "base",
+    "basefont",
+    "blockquote",
+    "body",
+    "caption",
+    "center",
+    "col",
+    "colgroup",
+    "dd",
+    "details",
+    "dialog",
+    "dir",
+    "div",
+    "dl",
+    "dt",
+    "fieldset",
+    "figcaption",
+    "figure",
+    "footer",
+    "form",
+    "frame",
+    "frameset",
+    "h1",
+    "h2",
+    "h3",
+    "h4",
+    "h5",
+    "h6",
+    "head",
+    "header",
+    "hr",
+    "html",
+    "iframe",
+    "legend",
+    "li",
+    "link",
+    "main",
+    "menu",
+    "menuitem",
+    "nav",
+    "noframes",
+    "ol",
+    "optgroup",
+    "option",
+    "p",
+    "param",
+    "search",
+    "section",
+    "summary",
+    "table",
+    "tbody",
+    "td",
+    "tfoot",
+    "th",
+    "thead",
+    "title",
+    "tr",
+    "track",
+    "ul",
+];
+
+impl BlockTracker {
+    /// Returns the literal block the next line would be inside of.
+    pub(crate) fn open_block(&self) -> Option<LiteralBlock> {
+        self.open
+    }
+
+    /// Feed the next line to the tracker.
+    ///
+    /// Returns whether the line opens, closes, or is inside of a literal block.
+    pub(crate) fn is_literal(&mut self, line: &str) -> bool {
+        match self.open {
+            Some(LiteralBlock::Fence { marker, len }) => {
+                if is_closing_fence(line, marker, len) {
+                    self.open = None;
+                }
+                true
+            }
+            Some(LiteralBlock::Html { terminator }) => {
+                if line.to_ascii_lowercase().contains(terminator) {
+                    self.open = None;
+                }
+                true
+            }
+            Some(LiteralBlock::HtmlParagraph) => {
+                if line.trim().is_empty() {
+                    self.open = None;
+                }
+                true
+            }
+            None => {
+                self.open = open_literal_block(line);
+                self.open.is_some()
+            }
+        }
+    }
+}
+
+/// Strips up to three spaces of indentation,
+/// returns `None` if the line is indented any further.
+fn strip_block_indent(line: &str) -> Option<&str> {
+    let trimmed = line.trim_start_matches(' ');
+    (line.len() - trimmed.len() <= 3).then_some(trimmed)
+}
+
+fn open_literal_block(line: &str) -> Option<LiteralBlock> {
+    let line = strip_block_indent(line)?;
+
+    if let Some(marker @ ('`' | '~')) = line.chars().next() {
+        let len = line.len() - line.trim_start_matches(marker).len();
+        let info = &line[len..];
+
+        if len >= 3 && !(marker == '`' && info.contains('`')) {
+            return Some(LiteralBlock::Fence { marker, len });
+        }
+
+        return None;
+    }
+
+    let lowercase = line.to_ascii_lowercase();
+
+    for &(start, terminator) in HTML_BLOCKS {
+        let Some(rest) = lowercase.strip_prefix(start) else {
+            continue;
+        };
+
+        // tag names have to end right after the start, special markers don't
+        let is_tag = start[1..].chars().all(|ch| ch.is_ascii_alphabetic());
+        if is_tag && !rest.is_empty() && !rest.starts_with([' ', '\t', '>']) {
+            continue;
+        }
+
+        if start == "<!" && !rest.starts_with(|ch: char| ch.is_ascii_alphabetic()) {
+            continue;
+        }
+
+        return (!rest.contains(terminator)).then_some(LiteralBlock::Html { terminator });
+    }
+
+    let tag = lowercase
+        .strip_prefix("</")
+        .or_else(|| lowercase.strip_prefix('<'))?;
+    let name_len = tag
+        .find(|ch: char| !ch.is_ascii_alphanumeric())
+        .unwrap_or(tag.len());
+    let (name, rest) = tag.split_at(name_len);
+
+    let is_block_tag = HTML_BLOCK_TAGS.contains(&name)
+        && (rest.is_empty() || rest.starts_with([' ', '\t', '>']) || rest.starts_with("/>"));
+
+    is_block_tag.then_some(LiteralBlock::HtmlParagraph)
+}
+
+fn is_closing_fence(line: &str, marker: char, len: usize) -> bool {
+    let Some(line) = strip_block_indent(line) else {
+        return false;
+    };
+
+    let rest = line.trim_start_matches(marker);
+    line.len() - rest.len() >= len && rest.trim().is_empty()
+}
+
 fn strip_suffix_newline(input: &str) -> Option<&str> {
     input
         .strip_suffix('\n')
@@ -24,36 +518,29 @@ fn empty_or_else<'a>(input: &'a str, f: impl FnOnce() -> Option<&'a str>) -> Opt
     input.is_empty().then_some(input).or_else(f)
 }
 
-impl<'a> Iterator for Slides<'a> {
+impl<'a, S: SlideSplitter> Iterator for Slides<'a, S> {
     type Item = &'a str;
 
     fn next(&mut self) -> Option<Self::Item> {
-        if self.string.is_empty() {
-            return None;
-        }
+        self.next_with_boundary().map(|(slide, _)| slide)
+    }
+}
 
-        let mut break_points = {
-            let mut break_point = 0;
-            self.string.split_inclusive("---").map(move |section| {
-                break_point += section.len();
-                break_point
-            })
-        };
+impl<'a, S: SlideSplitter> Iterator for Cards<'a, S> {
+    type Item = Vec<&'a str>;
 
-        let (slide, rest) = break_points
-            .find_map(|break_point| {
-                let (slide, rest) = self.string.split_at(break_point);
+    fn next(&mut self) -> Option<Self::Item> {
+        let mut card = vec![];
 
-                let slide = slide.strip_suffix("---").unwrap_or(slide);
-                let slide = empty_or_else(slide, || strip_suffix_newline(slide))?;
-                let rest = empty_or_else(rest, || strip_prefix_newline(rest))?;
+        while let Some((slide, boundary)) = self.slides.next_with_boundary() {
+            card.push(slide);
 
-                Some((slide, rest))
-            })
-            .unwrap_or((self.string, ""));
+            if boundary != Some(Boundary::Slide) {
+                break;
+            }
+        }
 
-        self.string = rest;
-        Some(slide)
+        (!card.is_empty()).then_some(card)
     }
 }
 
@@ -61,7 +548,46 @@ impl<'a> Iterator for Slides<'a> {
 mod tests {
     use indoc::indoc;
 
-    use super::Slides;
+    use super::{Boundary, Cards, LineSplitter, Slide, Slides};
+
+    mod slide {
+        use super::*;
+
+        #[test]
+        fn no_metadata() {
+            let slide = Slide::parse("+++ not metadata\nbody");
+            assert_eq!(slide.metadata, None);
+            assert_eq!(slide.body, "+++ not metadata\nbody");
+        }
+
+        #[test]
+        fn unclosed_metadata() {
+            let slide = Slide::parse("+++\nid: one\nbody");
+            assert_eq!(slide.metadata, None);
+            assert_eq!(slide.body, "+++\nid: one\nbody");
+        }
+
+        #[test]
+        fn empty_metadata() {
+            let slide = Slide::parse("+++\n+++\nbody");
+            assert_eq!(slide.metadata, Some(""));
+            assert_eq!(slide.body, "body");
+        }
+
+        #[test]
+        fn metadata_only() {
+            let slide = Slide::parse("+++\nid: one\n+++");
+            assert_eq!(slide.metadata, Some("id: one\n"));
+            assert_eq!(slide.body, "");
+        }
+
+        #[test]
+        fn carriage_return() {
+            let slide = Slide::parse("+++\r\nid: one\r\n+++\r\nbody");
+            assert_eq!(slide.metadata, Some("id: one\r\n"));
+            assert_eq!(slide.body, "body");
+        }
+    }
 
     #[test]
     fn empty() {
@@ -267,4 +793,199 @@ mod tests {
         assert_eq!(slides.next(), Some(""));
         assert_eq!(slides.next(), None);
     }
+
+    #[test]
+    fn custom_separator() {
+        let mut slides = Slides::with_splitter(
+            indoc! {"
+                Hello, world!
+                ---
+                still the first slide
+                %%
+                Goodbye, world!
+            "}
+            .trim_end(),
+            LineSplitter::new("%%"),
+        );
+        assert_eq!(
+            slides.next(),
+            Some("Hello, world!\n---\nstill the first slide")
+        );
+        assert_eq!(slides.next(), Some("Goodbye, world!"));
+        assert_eq!(slides.next(), None);
+    }
+
+    #[test]
+    fn boundaries() {
+        let mut slides = Slides::with_splitter("a\n---\nb\n===\nc", LineSplitter::cards());
+        assert_eq!(
+            slides.next_with_boundary(),
+            Some(("a", Some(Boundary::Slide)))
+        );
+        assert_eq!(
+            slides.next_with_boundary(),
+            Some(("b", Some(Boundary::Card)))
+        );
+        assert_eq!(slides.next_with_boundary(), Some(("c", None)));
+        assert_eq!(slides.next_with_boundary(), None);
+    }
+
+    #[test]
+    fn default_ignores_card_separator() {
+        let slides: Vec<_> = Slides::new("a\n===\nb").collect();
+        assert_eq!(slides, ["a\n===\nb"]);
+    }
+
+    mod cards {
+        use super::*;
+
+        fn cards(input: &str) -> Vec<Vec<&str>> {
+            Cards::new(input, LineSplitter::cards()).collect()
+        }
+
+        #[test]
+        fn empty() {
+            assert!(cards("").is_empty());
+        }
+
+        #[test]
+        fn single_card() {
+            assert_eq!(cards("a\n---\nb"), [vec!["a", "b"]]);
+        }
+
+        #[test]
+        fn many_cards() {
+            assert_eq!(
+                cards(indoc! {"
+                    a
+                    ---
+                    b
+                    ===
+                    c
+                    ===
+                    d
+                    ---
+                    e
+                    ---
+                    f
+                "}),
+                [vec!["a", "b"], vec!["c"], vec!["d", "e", "f\n"]]
+            );
+        }
+
+        #[test]
+        fn trailing_card_separator() {
+            assert_eq!(cards("a\n===\nb\n==="), [vec!["a"], vec!["b"]]);
+        }
+
+        #[test]
+        fn leading_card_separator() {
+            assert_eq!(cards("===\na"), [vec![""], vec!["a"]]);
+        }
+    }
+
+    mod literal_blocks {
+        use super::*;
+
+        fn slides(input: &str) -> Vec<&str> {
+            Slides::new(input).collect()
+        }
+
+        #[test]
+        fn backtick_fence() {
+            let source = indoc! {"
+                ```yaml
+                ---
+                key: value
+                ```
+                ---
+                back
+            "};
+            assert_eq!(slides(source), ["```yaml\n---\nkey: value\n```", "back\n"]);
+        }
+
+        #[test]
+        fn tilde_fence() {
+            let source = "~~~\n---\n~~~\n---\nback";
+            assert_eq!(slides(source), ["~~~\n---\n~~~", "back"]);
+        }
+
+        #[test]
+        fn math_fence() {
+            let source = "```math\n---\n```\n---\nback";
+            assert_eq!(slides(source), ["```math\n---\n```", "back"]);
+        }
+
+        #[test]
+        fn longer_closing_fence() {
+            let source = "````\n```\n---\n`````\n---\nback";
+            assert_eq!(slides(source), ["````\n```\n---\n`````", "back"]);
+        }
+
+        #[test]
+        fn mismatched_fence() {
+            let source = "```\n~~~\n---\n```\n---\nback";
+            assert_eq!(slides(source), ["```\n~~~\n---\n```", "back"]);
+        }
+
+        #[test]
+        fn indented_fence() {
+            let source = "   ```\n---\n   ```\n---\nback";
+            assert_eq!(slides(source), ["   ```\n---\n   ```", "back"]);
+        }
+
+        #[test]
+        fn too_indented_fence() {
+            let source = "    ```\n---\nback";
+            assert_eq!(slides(source), ["    ```", "back"]);
+        }
+
+        #[test]
+        fn inline_code_is_not_a_fence() {
+            let source = "``` `code` ```\n---\nback";
+            assert_eq!(slides(source), ["``` `code` ```", "back"]);
+        }
+
+        #[test]
+        fn unclosed_fence() {
+            let source = "```\n---\nstill code";
+            assert_eq!(slides(source), [source]);
+        }
+
+        #[test]
+        fn fence_after_slide() {
+            let source = "front\n---\n```\n---\n```";
+            assert_eq!(slides(source), ["front", "```\n---\n```"]);
+        }
+
+        #[test]
+        fn html_comment() {
+            let source = "<!--\n---\n-->\n---\nback";
+            assert_eq!(slides(source), ["<!--\n---\n-->", "back"]);
+        }
+
+        #[test]
+        fn single_line_html_comment() {
+            let source = "<!-- note -->\n---\nback";
+            assert_eq!(slides(source), ["<!-- note -->", "back"]);
+        }
+
+        #[test]
+        fn pre_block() {
+            let source = "<PRE>\n\n---\n</PRE>\n---\nback";
+            assert_eq!(slides(source), ["<PRE>\n\n---\n</PRE>", "back"]);
+        }
+
+        #[test]
+        fn block_tag_until_blank_line() {
+            let source = "<div>\n---\n\n---\nback";
+            assert_eq!(slides(source), ["<div>\n---\n", "back"]);
+        }
+
+        #[test]
+        fn inline_tag() {
+            let source = "<span>\n---\nback";
+            assert_eq!(slides(source), ["<span>", "back"]);
+        }
+    }
 }
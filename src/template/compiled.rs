@@ -0,0 +1,109 @@
+use super::{
+    parse::{parse_root, Block},
+    split_document, Diagnostic, Environment, Error, Output, FRONT_MATTER_LOCATION,
+};
+
+/// A document parsed ahead of time, which can be rendered many times.
+///
+/// # Examples
+/// ```
+/// use flashmark::template::{self, CompiledTemplate};
+///
+/// let template = CompiledTemplate::compile(template::new_engine(), "@x squared is @(x * x)").unwrap();
+///
+/// for x in 1..=3_i64 {
+///     let mut scope = rhai::Scope::new();
+///     scope.push("x", x);
+///
+///     let output = template.render(scope).unwrap();
+///     assert_eq!(output, format!("{x} squared is {}\n", x * x));
+/// }
+/// ```
+pub struct CompiledTemplate {
+    engine: rhai::Shared<rhai::Engine>,
+    front_matter: Option<rhai::AST>,
+    root: Block<'static>,
+}
+
+impl CompiledTemplate {
+    /// Parse a document, compiling its front matter and every expression in it.
+    ///
+    /// Fails if anything has a syntax error.
+    pub fn compile(
+        engine: impl Into<rhai::Shared<rhai::Engine>>,
+        input: &str,
+    ) -> Result<Self, Error> {
+        Self::compile_in(&Environment::with_engine(engine), input)
+    }
+
+    /// Parse a document using the engine and loader of an environment.
+    ///
+    /// Variables in the environment's scope aren't used.
+    pub fn compile_in(env: &Environment, input: &str) -> Result<Self, Error> {
+        let engine = env.shared_engine();
+        let (front_matter, mut lines) = split_document(input);
+
+        let mut diagnostics = vec![];
+
+        let front_matter = match front_matter.map(|script| engine.compile(script)) {
+            Some(Ok(ast)) => Some(ast),
+            Some(Err(err)) => {
+                diagnostics.push(Diagnostic::front_matter(FRONT_MATTER_LOCATION, err.into()));
+                None
+            }
+            None => None,
+        };
+
+        // expressions are compiled without any variables in scope,
+        // so no value from a single render can be baked into the template,
+        // but with the functions of the front matter to tell filters apart
+        let mut env = env.without_scope();
+        if let Some(front_matter) = &front_matter {
+            env.add_functions(front_matter);
+        }
+
+        let (root, parse_diagnostics) = parse_root(&env, &mut lines);
+
+        diagnostics.extend(parse_diagnostics);
+
+        if !diagnostics.is_empty() {
+            return Err(Error { diagnostics });
+        }
+
+        Ok(Self {
+            engine,
+            front_matter,
+            root: root.into_owned(),
+        })
+    }
+
+    /// Create a fresh environment using the template's engine.
+    pub fn environment(&self) -> Environment {
+        Environment::with_engine(self.engine.clone())
+    }
+
+    /// Render the template with some variables in scope.
+    pub fn render(&self, scope: rhai::Scope<'static>) -> Result<String, Error> {
+        let env = Environment::with_scope(self.engine.clone(), scope);
+        self.render_lenient(env).into_result()
+    }
+
+    /// Render as much of the template as possible in an environment,
+    /// collecting diagnostics instead of failing.
+    ///
+    /// The front matter runs in the environment first,
+    /// so it can use any variable already in scope.
+    pub fn render_lenient(&self, mut env: Environment) -> Output {
+        let mut output = Output::new();
+
+        if let Some(front_matter) = &self.front_matter {
+            if let Err(err) = env.run_ast(front_matter) {
+                output.report(Diagnostic::front_matter(FRONT_MATTER_LOCATION, err));
+            }
+        }
+
+        self.root.render_root(&mut env, &mut output);
+
+        output
+    }
+}
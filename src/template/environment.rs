@@ -1,62 +1,243 @@
+use std::{cell::Cell, collections::HashSet, sync::Arc};
+
 use rhai::packages::Package;
 
+use super::{random, Formatter, TemplateLoader};
+use crate::slides::Separators;
+
 pub struct Environment {
-    engine: rhai::Engine,
+    engine: rhai::Shared<rhai::Engine>,
     scope: rhai::Scope<'static>,
     runtime: rhai::GlobalRuntimeState,
     funcs: Option<rhai::AST>,
+    separators: Separators,
+    generator: Option<rand::rngs::StdRng>,
+    loader: Option<Arc<dyn TemplateLoader + Send + Sync>>,
+    limits: Limits,
+    autoescape: bool,
+    formatter: Arc<Formatter>,
+    /// The amount of operations run by every script and expression so far.
+    operations: u64,
+}
+
+/// Limits on how much a template can render,
+/// so a buggy or malicious one can't run forever or use up all the memory.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Limits {
+    /// The maximum amount of iterations, of every loop combined.
+    pub max_iterations: Option<usize>,
+    /// The maximum length of the output, in bytes.
+    pub max_output_size: Option<usize>,
 }
 
 pub type RhaiIterator = Box<dyn Iterator<Item = Result<rhai::Dynamic, Box<rhai::EvalAltResult>>>>;
 
+thread_local! {
+    // building the standard package is expensive, so it's only done once per thread
+    static STANDARD_PACKAGE: rhai::Shared<rhai::Module> =
+        rhai::packages::StandardPackage::new().as_shared_module();
+
+    // the operations run by the environment currently evaluating on this thread,
+    // before and during the current evaluation
+    static OPERATIONS: Cell<(u64, u64)> = const { Cell::new((0, 0)) };
+}
+
 impl Environment {
+    /// Create an environment.
+    ///
+    /// The engine can be shared between many environments,
+    /// by passing in a [`rhai::Shared`] engine.
     pub fn new(
-        engine: rhai::Engine,
+        engine: impl Into<rhai::Shared<rhai::Engine>>,
         scope: rhai::Scope<'static>,
         funcs: Option<rhai::AST>,
     ) -> Self {
+        let engine = engine.into();
+
         // really messy code just to get the built-in iterators
         // TODO: find a better way to do this garbage
-        let mut runtime = rhai::GlobalRuntimeState::new(&engine);
-        runtime.push_import(
-            "global",
-            rhai::packages::StandardPackage::new().as_shared_module(),
-        );
+        let mut runtime = engine.new_global_runtime_state();
+        runtime.push_import("global", STANDARD_PACKAGE.with(rhai::Shared::clone));
 
         Self {
             engine,
             scope,
             runtime,
             funcs,
+            separators: Separators::default(),
+            generator: None,
+            loader: None,
+            limits: Limits::default(),
+            autoescape: true,
+            formatter: Arc::default(),
+            operations: 0,
         }
     }
 
-    pub fn with_engine(engine: rhai::Engine) -> Self {
+    pub fn with_engine(engine: impl Into<rhai::Shared<rhai::Engine>>) -> Self {
         Self::with_scope(engine, rhai::Scope::new())
     }
 
-    pub fn with_scope(engine: rhai::Engine, scope: rhai::Scope<'static>) -> Self {
+    pub fn with_scope(
+        engine: impl Into<rhai::Shared<rhai::Engine>>,
+        scope: rhai::Scope<'static>,
+    ) -> Self {
         Self::new(engine, scope, None)
     }
 
     pub fn try_with_script(
-        engine: rhai::Engine,
+        engine: impl Into<rhai::Shared<rhai::Engine>>,
         script: impl AsRef<str>,
     ) -> Result<Self, Box<rhai::EvalAltResult>> {
-        let ast = engine.compile(script)?;
+        let mut env = Self::with_engine(engine);
+        env.run_script(script)?;
+
+        Ok(env)
+    }
+
+    /// Run a script, keeping its variables in scope and its functions available
+    /// to every expression evaluated afterwards.
+    pub fn run_script(&mut self, script: impl AsRef<str>) -> Result<(), Box<rhai::EvalAltResult>> {
+        let ast = self.engine.compile(script)?;
+        self.run_ast(&ast)
+    }
 
-        let mut scope = rhai::Scope::new();
-        engine.run_ast_with_scope(&mut scope, &ast)?;
+    /// Run a compiled script, like [`Environment::run_script`].
+    pub fn run_ast(&mut self, ast: &rhai::AST) -> Result<(), Box<rhai::EvalAltResult>> {
+        with_operations(&mut self.operations, || {
+            random::with_generator(&mut self.generator, || {
+                self.engine.run_ast_with_scope(&mut self.scope, ast)
+            })
+        })?;
 
-        let funcs = ast.has_functions().then(|| ast.clone_functions_only());
+        self.add_functions(ast);
 
-        Ok(Self::new(engine, scope, funcs))
+        Ok(())
+    }
+
+    /// Make the functions defined in a script available to every expression
+    /// evaluated afterwards, without running it.
+    pub(crate) fn add_functions(&mut self, ast: &rhai::AST) {
+        if ast.has_functions() {
+            let funcs = ast.clone_functions_only();
+            self.funcs = Some(match self.funcs.take() {
+                Some(existing) => existing.merge(&funcs),
+                None => funcs,
+            });
+        }
+    }
+
+    /// Returns the names of every function expressions can call without a namespace,
+    /// whether it's registered with the engine or defined by a script.
+    pub(crate) fn function_names(&self) -> HashSet<String> {
+        let mut names: HashSet<_> = self
+            .engine
+            .collect_fn_metadata(
+                None,
+                |info| {
+                    info.namespace
+                        .is_empty()
+                        .then(|| info.metadata.name.to_string())
+                },
+                true,
+            )
+            .into_iter()
+            .collect();
+
+        if let Some(funcs) = &self.funcs {
+            names.extend(funcs.iter_functions().map(|func| func.name.to_string()));
+        }
+
+        names
+    }
+
+    pub fn engine(&self) -> &rhai::Engine {
+        &self.engine
+    }
+
+    pub(crate) fn shared_engine(&self) -> rhai::Shared<rhai::Engine> {
+        self.engine.clone()
+    }
+
+    /// Create an environment with the same engine, separators and loader,
+    /// but nothing in scope.
+    pub(crate) fn without_scope(&self) -> Self {
+        let mut env = Self::with_engine(self.engine.clone());
+        env.separators = self.separators.clone();
+        env.loader = self.loader.clone();
+        env.limits = self.limits;
+        env.autoescape = self.autoescape;
+        env.formatter = self.formatter.clone();
+
+        env
     }
 
     pub fn scope_mut(&mut self) -> &mut rhai::Scope<'static> {
         &mut self.scope
     }
 
+    /// Returns the lines written by `@slide` and `@card`.
+    pub fn separators(&self) -> &Separators {
+        &self.separators
+    }
+
+    pub fn set_separators(&mut self, separators: Separators) {
+        self.separators = separators;
+    }
+
+    /// Returns the loader used by `@include`.
+    pub fn loader(&self) -> Option<&(dyn TemplateLoader + Send + Sync)> {
+        self.loader.as_deref()
+    }
+
+    pub fn set_loader(&mut self, loader: impl TemplateLoader + Send + Sync + 'static) {
+        self.loader = Some(Arc::new(loader));
+    }
+
+    pub fn limits(&self) -> Limits {
+        self.limits
+    }
+
+    pub fn set_limits(&mut self, limits: Limits) {
+        self.limits = limits;
+    }
+
+    /// Returns whether interpolated values are escaped,
+    /// so they can't change the structure of the deck.
+    pub fn autoescape(&self) -> bool {
+        self.autoescape
+    }
+
+    /// Choose whether interpolated values are escaped, which they are by default.
+    ///
+    /// Values written with `@!(...)` or `@raw(...)` are never escaped.
+    pub fn set_autoescape(&mut self, autoescape: bool) {
+        self.autoescape = autoescape;
+    }
+
+    /// Returns the formatter writing interpolated values into the output.
+    pub fn formatter(&self) -> &Formatter {
+        &self.formatter
+    }
+
+    pub fn formatter_mut(&mut self) -> &mut Formatter {
+        Arc::make_mut(&mut self.formatter)
+    }
+
+    /// Format interpolated values with `formatter`,
+    /// which can be shared between many environments.
+    pub fn set_formatter(&mut self, formatter: impl Into<Arc<Formatter>>) {
+        self.formatter = formatter.into();
+    }
+
+    /// Seed the generator of the `rand` module,
+    /// so every render in this environment draws the same numbers.
+    ///
+    /// Without a seed, numbers are drawn from the thread's generator.
+    pub fn set_seed(&mut self, seed: u64) {
+        self.generator = Some(random::generator(seed));
+    }
+
     pub fn compile_expr(&self, script: impl AsRef<str>) -> Result<rhai::AST, rhai::ParseError> {
         self.engine
             .compile_expression_with_scope(&self.scope, script)
@@ -74,10 +255,26 @@ impl Environment {
             ast = Cow::Owned(funcs.merge(&ast));
         }
 
-        self.engine.eval_ast_with_scope(&mut self.scope, &ast)
+        with_operations(&mut self.operations, || {
+            random::with_generator(&mut self.generator, || {
+                self.engine.eval_ast_with_scope(&mut self.scope, &ast)
+            })
+        })
     }
 
+    /// Returns an iterator over a value.
+    ///
+    /// Object maps are iterated as `[key, value]` pairs, ordered by key.
     pub fn get_iter(&self, value: rhai::Dynamic) -> Result<RhaiIterator, rhai::Dynamic> {
+        if value.is_map() {
+            let map = value.cast::<rhai::Map>();
+            let pairs = map.into_iter().map(|(key, value)| {
+                Ok(vec![rhai::ImmutableString::from(key.as_str()).into(), value].into())
+            });
+
+            return Ok(Box::new(pairs));
+        }
+
         if let Some(iter_fn) = self.runtime.get_iter(value.type_id()) {
             Ok(iter_fn(value))
         } else {
@@ -85,3 +282,63 @@ impl Environment {
         }
     }
 }
+
+/// Run `f`, counting the operations it runs on top of `operations`.
+fn with_operations<T>(operations: &mut u64, f: impl FnOnce() -> T) -> T {
+    let previous = OPERATIONS.replace((*operations, 0));
+    let result = f();
+    let (before, during) = OPERATIONS.replace(previous);
+    *operations = before + during;
+
+    result
+}
+
+/// Keeps track of the operations run by the environment evaluating on this thread,
+/// stopping it once they're more than `max` combined.
+///
+/// Meant to be called by [`rhai::Engine::on_progress`],
+/// since an engine only limits the operations of a single evaluation.
+pub(crate) fn limit_total_operations(operations: u64, max: u64) -> Option<rhai::Dynamic> {
+    let (before, _) = OPERATIONS.get();
+    OPERATIONS.set((before, operations));
+
+    (before + operations > max).then(|| format!("ran more than {max} operations").into())
+}
+
+/// Fails like rhai does when a host function would build a string longer than
+/// the engine allows, since rhai only checks the strings a function returns,
+/// after they're allocated.
+pub(crate) fn check_string_size(
+    ctx: &rhai::NativeCallContext,
+    length: usize,
+) -> Result<(), Box<rhai::EvalAltResult>> {
+    let max = ctx.engine().max_string_size();
+
+    if max > 0 && length > max {
+        let error = rhai::EvalAltResult::ErrorDataTooLarge(
+            "Length of string".to_string(),
+            rhai::Position::NONE,
+        );
+        return Err(error.into());
+    }
+
+    Ok(())
+}
+
+impl Limits {
+    /// No limits at all, for templates that are trusted.
+    pub const UNLIMITED: Limits = Limits {
+        max_iterations: None,
+        max_output_size: None,
+    };
+}
+
+impl Default for Limits {
+    /// Generous limits, which no reasonable deck should reach.
+    fn default() -> Self {
+        Self {
+            max_iterations: Some(1_000_000),
+            max_output_size: Some(16 * 1024 * 1024),
+        }
+    }
+}
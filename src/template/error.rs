@@ -0,0 +1,139 @@
+use std::fmt;
+
+use super::Location;
+use crate::slides::Boundary;
+
+/// The different things that can go wrong while rendering a template.
+#[derive(Debug, thiserror::Error)]
+pub enum DiagnosticKind {
+    #[error("front matter: {0}")]
+    FrontMatter(Box<rhai::EvalAltResult>),
+    #[error("{0}")]
+    Parse(rhai::ParseErrorType),
+    #[error("{0}")]
+    Eval(Box<rhai::EvalAltResult>),
+    /// A script went over one of the engine's limits,
+    /// like the maximum amount of operations of [`new_safe_engine`](super::new_safe_engine).
+    #[error("resource limit exceeded: {0}")]
+    ResourceLimit(Box<rhai::EvalAltResult>),
+    #[error("value of type '{0}' is not iterable")]
+    NotIterable(String),
+    #[error("no separator is configured for {0} boundaries")]
+    NoSeparator(Boundary),
+    #[error("expected a quoted path to include")]
+    IncludePath,
+    #[error("no template loader is configured")]
+    NoLoader,
+    #[error("failed to load '{0}': {1}")]
+    Load(String, std::io::Error),
+    #[error("'{0}' includes itself")]
+    IncludeCycle(String),
+    #[error("cannot destructure a value of type '{0}' into {1}")]
+    Destructure(String, String),
+    #[error("'@{0}' can only be used inside of a loop")]
+    OutsideLoop(String),
+    /// A line like `@card` while a variable of the same name is in scope,
+    /// which is always a directive rather than the variable.
+    #[error("'@{0}' is a directive, write '@({0})' to write the variable '{0}'")]
+    ReservedName(String),
+    #[error("loops ran more than {0} times in total")]
+    IterationLimit(usize),
+    #[error("output is longer than {0} bytes")]
+    OutputLimit(usize),
+    #[error("expected '@case' or '@else' before this line")]
+    ExpectedCase,
+    #[error("expected a macro definition like '@macro name(params)'")]
+    MacroDefinition,
+    #[error("macro '{0}' takes {1} arguments, but {2} were given")]
+    MacroArguments(String, usize, usize),
+    #[error("unterminated comment, expected '*@'")]
+    UnterminatedComment,
+    /// An expression like `@(...)` that isn't closed by the end of the line,
+    /// along with what was expected to close it.
+    #[error("unterminated expression, expected '{0}'")]
+    UnterminatedExpression(&'static str),
+    /// A diagnostic from an included template,
+    /// located at the `@include` directive.
+    #[error("in '{0}': {1}")]
+    Included(String, Box<Diagnostic>),
+}
+
+/// A single error found while rendering a template,
+/// along with where in the template it came from.
+#[derive(Debug, thiserror::Error)]
+#[error("{kind} ({location})")]
+pub struct Diagnostic {
+    pub location: Location,
+    pub kind: DiagnosticKind,
+}
+
+/// Every diagnostic collected while rendering a template.
+#[derive(Debug, thiserror::Error)]
+#[error("{}", DiagnosticList(.diagnostics))]
+pub struct Error {
+    pub diagnostics: Vec<Diagnostic>,
+}
+
+struct DiagnosticList<'a>(&'a [Diagnostic]);
+
+impl Diagnostic {
+    pub fn new(location: Location, kind: DiagnosticKind) -> Self {
+        Self { location, kind }
+    }
+
+    /// Create a diagnostic from an error parsing the script starting at `location`.
+    pub fn parse(location: Location, error: rhai::ParseError) -> Self {
+        let rhai::ParseError(kind, position) = error;
+
+        Self::new(location.offset(position), DiagnosticKind::Parse(*kind))
+    }
+
+    /// Create a diagnostic from an error evaluating the script starting at `location`.
+    pub fn eval(location: Location, error: Box<rhai::EvalAltResult>) -> Self {
+        Self::from_eval_error(location, error, DiagnosticKind::Eval)
+    }
+
+    /// Create a diagnostic from an error running the front matter starting at `location`.
+    pub fn front_matter(location: Location, error: Box<rhai::EvalAltResult>) -> Self {
+        Self::from_eval_error(location, error, DiagnosticKind::FrontMatter)
+    }
+
+    fn from_eval_error(
+        location: Location,
+        mut error: Box<rhai::EvalAltResult>,
+        kind: impl FnOnce(Box<rhai::EvalAltResult>) -> DiagnosticKind,
+    ) -> Self {
+        use rhai::EvalAltResult::*;
+
+        let position = error.take_position();
+
+        let kind = match error.unwrap_inner() {
+            ErrorTooManyOperations(_)
+            | ErrorTerminated(..)
+            | ErrorTooManyVariables(_)
+            | ErrorTooManyModules(_)
+            | ErrorStackOverflow(_)
+            | ErrorDataTooLarge(..) => DiagnosticKind::ResourceLimit(error),
+            _ => kind(error),
+        };
+
+        Self::new(location.offset(position), kind)
+    }
+}
+
+impl fmt::Display for DiagnosticList<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut diagnostics = self.0.iter();
+
+        match diagnostics.next() {
+            Some(first) => write!(f, "{}", first)?,
+            None => write!(f, "failed to render template")?,
+        }
+
+        for diagnostic in diagnostics {
+            write!(f, "\n{}", diagnostic)?;
+        }
+
+        Ok(())
+    }
+}
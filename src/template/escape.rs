@@ -0,0 +1,303 @@
+//! Escaping of the values interpolated into templates,
+//! so they're rendered as text instead of changing the structure of the deck.
+//!
+//! Values are escaped depending on where they're written:
+//! ```
+//! use flashmark::template::escape::{escape, EscapeContext};
+//!
+//! let markdown = EscapeContext::Markdown { at_line_start: true };
+//! assert_eq!(escape("# *not* a heading", markdown), r"\# \*not\* a heading");
+//! assert_eq!(escape("<b>", EscapeContext::Html), "&lt;b&gt;");
+//!
+//! // code is taken literally, but can't be closed by the value
+//! let code_span = EscapeContext::CodeSpan { backticks: 1 };
+//! assert_eq!(escape("<b>", code_span), "<b>");
+//! assert_eq!(escape("a`b", code_span), "a``b");
+//! ```
+
+use std::borrow::Cow;
+
+/// Where a value is written, which decides how it's escaped.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EscapeContext {
+    /// Markdown text, escaped with backslashes.
+    Markdown {
+        /// Whether only whitespace comes before the value on its line,
+        /// where it could start a block like a heading, a list or a separator.
+        at_line_start: bool,
+    },
+    /// A raw HTML block, escaped with character references.
+    Html,
+    /// A fenced code block, which is taken literally until a line closes it.
+    CodeBlock {
+        /// Whether only whitespace comes before the value on its line.
+        at_line_start: bool,
+    },
+    /// A code span or inline math, which is taken literally until a run of backticks closes it.
+    CodeSpan {
+        /// The length of the run of backticks opening the span.
+        backticks: usize,
+    },
+    /// The metadata block at the start of a slide,
+    /// which is taken literally until a line of `+++` closes it.
+    Metadata {
+        /// Whether only whitespace comes before the value on its line.
+        at_line_start: bool,
+    },
+}
+
+/// Characters escaped anywhere in markdown text,
+/// since they start inline markup, HTML, entities or math,
+/// or could close the destination of a link the value is written into.
+const MARKDOWN_INLINE: &[char] = &[
+    '\\', '`', '*', '_', '[', ']', '(', ')', '<', '>', '&', '|', '~', '$',
+];
+
+/// Written before a line that could close a literal block or separate slides,
+/// so it's taken as text instead.
+const ZERO_WIDTH_SPACE: char = '\u{200b}';
+
+pub fn escape(text: &str, context: EscapeContext) -> Cow<'_, str> {
+    match context {
+        EscapeContext::Markdown { at_line_start } => escape_markdown(text, at_line_start),
+        EscapeContext::Html => escape_html(text),
+        EscapeContext::CodeBlock { at_line_start } => escape_code_block(text, at_line_start),
+        EscapeContext::CodeSpan { backticks } => escape_code_span(text, backticks),
+        EscapeContext::Metadata { at_line_start } => escape_metadata(text, at_line_start),
+    }
+}
+
+/// Escape text written into markdown.
+///
+/// At the start of a line, punctuation starting a block is escaped as well,
+/// like `#`, `-`, `>`, or the `.` of `1.`.
+pub fn escape_markdown(text: &str, at_line_start: bool) -> Cow<'_, str> {
+    let mut escaped = String::with_capacity(text.len());
+
+    let mut at_line_start = at_line_start;
+    let mut list_marker = None;
+
+    for (i, ch) in text.char_indices() {
+        if at_line_start && !ch.is_whitespace() {
+            at_line_start = false;
+
+            // the marker of an ordered list follows its number, like `1.` or `2)`,
+            // and is followed by whitespace unlike the point of `1.5`
+            if ch.is_ascii_digit() {
+                let end = text[i..]
+                    .find(|ch: char| !ch.is_ascii_digit())
+                    .map(|len| i + len);
+
+                list_marker = end.filter(|&end| {
+                    let mut rest = text[end..].chars();
+                    matches!(rest.next(), Some('.' | ')'))
+                        && rest.next().is_none_or(char::is_whitespace)
+                });
+            }
+
+            if ch.is_ascii_punctuation() {
+                escaped.push('\\');
+            }
+        } else if MARKDOWN_INLINE.contains(&ch) || list_marker == Some(i) {
+            escaped.push('\\');
+        }
+
+        if ch == '\n' {
+            at_line_start = true;
+        }
+
+        escaped.push(ch);
+    }
+
+    match escaped.len() == text.len() {
+        true => text.into(),
+        false => escaped.into(),
+    }
+}
+
+/// Escape text written into a fenced code block.
+///
+/// Its contents are taken literally, so only lines that could close the block
+/// or separate slides are changed, by starting them with a zero width space.
+pub fn escape_code_block(text: &str, at_line_start: bool) -> Cow<'_, str> {
+    let mut escaped = String::with_capacity(text.len());
+
+    for (i, line) in text.split_inclusive('\n').enumerate() {
+        if (i > 0 || at_line_start) && is_structural(line) {
+            escaped.push(ZERO_WIDTH_SPACE);
+        }
+
+        escaped.push_str(line);
+    }
+
+    match escaped.len() == text.len() {
+        true => text.into(),
+        false => escaped.into(),
+    }
+}
+
+/// Escape text written into a code span opened by a run of `backticks`.
+///
+/// Its contents are taken literally, so line breaks are written as spaces like markdown
+/// renders them, and runs of as many backticks as the opening one are lengthened,
+/// so they don't close the span.
+/// Backticks at either end are kept apart from the ones around the value
+/// by a zero width space.
+pub fn escape_code_span(text: &str, backticks: usize) -> Cow<'_, str> {
+    if !text.contains(['\n', '\r', '`']) {
+        return text.into();
+    }
+
+    let text = join_lines(text);
+    let mut escaped = String::with_capacity(text.len() + 2);
+
+    if text.starts_with('`') {
+        escaped.push(ZERO_WIDTH_SPACE);
+    }
+
+    let mut rest = &*text;
+
+    while let Some(start) = rest.find('`') {
+        let run = rest[start..].len() - rest[start..].trim_start_matches('`').len();
+        let len = if run == backticks { run + 1 } else { run };
+
+        escaped.push_str(&rest[..start]);
+        escaped.extend(std::iter::repeat_n('`', len));
+        rest = &rest[start + run..];
+    }
+
+    escaped.push_str(rest);
+
+    if text.ends_with('`') {
+        escaped.push(ZERO_WIDTH_SPACE);
+    }
+
+    escaped.into()
+}
+
+/// Escape text written into a metadata block.
+///
+/// Its contents are taken literally, so line breaks are written as spaces
+/// to keep the value on its line, which is changed like in a code block.
+pub fn escape_metadata(text: &str, at_line_start: bool) -> Cow<'_, str> {
+    let text = join_lines(text);
+
+    match at_line_start && is_structural(&text) {
+        true => format!("{ZERO_WIDTH_SPACE}{text}").into(),
+        false => text,
+    }
+}
+
+/// Replaces every line break with a space.
+fn join_lines(text: &str) -> Cow<'_, str> {
+    match text.contains(['\n', '\r']) {
+        true => text.replace("\r\n", " ").replace(['\n', '\r'], " ").into(),
+        false => text.into(),
+    }
+}
+
+/// Returns whether a line could change the structure of the deck,
+/// even in a literal block: a code fence, or a line like `---` or `+++`
+/// that could be a separator or close a metadata block.
+fn is_structural(line: &str) -> bool {
+    let mut chars = line.trim().chars();
+
+    match chars.next() {
+        Some(ch @ ('`' | '~' | '-' | '=' | '+' | '*' | '_')) => {
+            chars.take(2).filter(|&other| other == ch).count() == 2
+        }
+        _ => false,
+    }
+}
+
+/// Escape text written into HTML.
+pub fn escape_html(text: &str) -> Cow<'_, str> {
+    if !text.contains(['&', '<', '>', '"', '\'']) {
+        return text.into();
+    }
+
+    let mut escaped = String::with_capacity(text.len());
+
+    for ch in text.chars() {
+        match ch {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            _ => escaped.push(ch),
+        }
+    }
+
+    escaped.into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn markdown() {
+        assert_eq!(escape_markdown("plain text, 1.5", false), "plain text, 1.5");
+        assert_eq!(
+            escape_markdown("*bold* <script> [link](x) a_b", false),
+            r"\*bold\* \<script\> \[link\]\(x\) a\_b"
+        );
+        assert_eq!(
+            escape_markdown("x) ![img](javascript:alert(1)", false),
+            r"x\) !\[img\]\(javascript:alert\(1\)"
+        );
+        assert_eq!(escape_markdown("- item", false), "- item");
+        assert_eq!(escape_markdown("  - item", true), r"  \- item");
+        assert_eq!(escape_markdown("---", true), r"\---");
+        assert_eq!(escape_markdown("12. item", true), r"12\. item");
+        assert_eq!(escape_markdown("12 items", true), "12 items");
+        assert_eq!(escape_markdown("3.14", true), "3.14");
+        assert_eq!(escape_markdown("3.", true), r"3\.");
+        assert_eq!(escape_markdown("a\n# b\n+++", false), "a\n\\# b\n\\+++");
+    }
+
+    #[test]
+    fn code_block() {
+        assert_eq!(escape_code_block("let x = 1;\n", true), "let x = 1;\n");
+        assert_eq!(
+            escape_code_block("```\n---\n  ~~~ rust\n+++", false),
+            "```\n\u{200b}---\n\u{200b}  ~~~ rust\n\u{200b}+++"
+        );
+        assert_eq!(escape_code_block("---", true), "\u{200b}---");
+        assert_eq!(escape_code_block("-- x", true), "-- x");
+    }
+
+    #[test]
+    fn code_span() {
+        assert_eq!(escape_code_span("a\nb\r\nc", 1), "a b c");
+        assert_eq!(
+            escape_code_span("`a` ``b``", 1),
+            "\u{200b}``a`` ``b``\u{200b}"
+        );
+        assert_eq!(escape_code_span("a ``b`` c", 2), "a ```b``` c");
+        assert!(matches!(escape_code_span("<b>", 1), Cow::Borrowed(_)));
+    }
+
+    #[test]
+    fn metadata() {
+        let context = EscapeContext::Metadata {
+            at_line_start: false,
+        };
+        assert_eq!(escape("a\n+++\nb", context), "a +++ b");
+
+        let context = EscapeContext::Metadata {
+            at_line_start: true,
+        };
+        assert_eq!(escape("+++", context), "\u{200b}+++");
+    }
+
+    #[test]
+    fn html() {
+        assert_eq!(
+            escape_html(r#"<a href="x">'&'</a>"#),
+            "&lt;a href=&quot;x&quot;&gt;&#39;&amp;&#39;&lt;/a&gt;"
+        );
+        assert!(matches!(escape_html("plain"), Cow::Borrowed(_)));
+    }
+}
@@ -0,0 +1,283 @@
+//! The functions templates can use as filters, like `@(price | currency)`.
+//!
+//! A filter is called with the value piped into it as its first argument,
+//! so any function can be a filter, including those defined in the front matter.
+//! A pipe that isn't followed by a function, like in `@(flags | 2)`, is a bitwise or.
+
+use rhai::plugin::*;
+
+use super::environment::check_string_size;
+
+/// Build the module, which [`new_engine`](super::new_engine) registers globally.
+pub fn module() -> rhai::Module {
+    let mut module = rhai::Module::new();
+
+    combine_with_exported_module!(&mut module, "numbers", number_filters);
+    combine_with_exported_module!(&mut module, "text", text_filters);
+
+    module
+}
+
+/// Insert a comma between every group of three digits.
+fn group_thousands(digits: &str) -> String {
+    let mut grouped = String::with_capacity(digits.len() + digits.len() / 3);
+
+    for (i, digit) in digits.chars().enumerate() {
+        if i > 0 && (digits.len() - i).is_multiple_of(3) {
+            grouped.push(',');
+        }
+
+        grouped.push(digit);
+    }
+
+    grouped
+}
+
+/// Format a number with its integer part grouped by thousands.
+fn format_thousands(formatted: &str) -> String {
+    let (sign, unsigned) = match formatted.strip_prefix('-') {
+        Some(unsigned) => ("-", unsigned),
+        None => ("", formatted),
+    };
+
+    match unsigned.split_once('.') {
+        Some((int, fraction)) => format!("{sign}{}.{fraction}", group_thousands(int)),
+        None => format!("{sign}{}", group_thousands(unsigned)),
+    }
+}
+
+/// Clamp an amount of digits after the decimal point to what floats can hold.
+fn clamp_digits(digits: rhai::INT) -> usize {
+    digits.clamp(0, 17) as usize
+}
+
+/// Pad a value with a character up to a width,
+/// failing instead of padding past the longest string the engine allows.
+fn pad(
+    ctx: &NativeCallContext,
+    value: Dynamic,
+    width: rhai::INT,
+    fill: char,
+    left: bool,
+) -> Result<String, Box<EvalAltResult>> {
+    let text = value.to_string();
+    let width = usize::try_from(width.max(0)).unwrap_or(usize::MAX);
+    let padding = width.saturating_sub(text.chars().count());
+
+    let length = padding
+        .saturating_mul(fill.len_utf8())
+        .saturating_add(text.len());
+    check_string_size(ctx, length)?;
+
+    let padding: String = std::iter::repeat_n(fill, padding).collect();
+
+    Ok(match left {
+        true => format!("{padding}{text}"),
+        false => format!("{text}{padding}"),
+    })
+}
+
+#[export_module]
+mod number_filters {
+    use rhai::{FLOAT, INT};
+
+    /// Round a number to an amount of digits after the decimal point.
+    #[rhai_fn(name = "round")]
+    pub fn round_to(value: FLOAT, digits: INT) -> FLOAT {
+        let factor = FLOAT::powi(10.0, clamp_digits(digits) as i32);
+        (value * factor).round() / factor
+    }
+
+    /// Integers are already rounded.
+    #[rhai_fn(name = "round")]
+    pub fn round_int_to(value: INT, _digits: INT) -> INT {
+        value
+    }
+
+    /// Format a number with a fixed amount of digits after the decimal point.
+    pub fn fixed(value: FLOAT, digits: INT) -> String {
+        format!("{value:.*}", clamp_digits(digits))
+    }
+
+    #[rhai_fn(name = "fixed")]
+    pub fn fixed_int(value: INT, digits: INT) -> String {
+        fixed(value as FLOAT, digits)
+    }
+
+    /// Format a number with commas between groups of thousands, like `1,234,567`.
+    pub fn thousands(value: INT) -> String {
+        format_thousands(&value.to_string())
+    }
+
+    #[rhai_fn(name = "thousands")]
+    pub fn thousands_float(value: FLOAT) -> String {
+        format_thousands(&value.to_string())
+    }
+
+    /// Format an amount of money in dollars, like `$1,234.50`.
+    pub fn currency(value: FLOAT) -> String {
+        currency_with_symbol(value, "$")
+    }
+
+    #[rhai_fn(name = "currency")]
+    pub fn currency_int(value: INT) -> String {
+        currency(value as FLOAT)
+    }
+
+    /// Format an amount of money with a currency symbol before it.
+    #[rhai_fn(name = "currency")]
+    pub fn currency_with_symbol(value: FLOAT, symbol: &str) -> String {
+        let sign = if value < 0.0 { "-" } else { "" };
+        let amount = format_thousands(&format!("{:.2}", value.abs()));
+
+        format!("{sign}{symbol}{amount}")
+    }
+
+    #[rhai_fn(name = "currency")]
+    pub fn currency_int_with_symbol(value: INT, symbol: &str) -> String {
+        currency_with_symbol(value as FLOAT, symbol)
+    }
+
+    /// Format a ratio as a whole percentage, like `0.25` as `25%`.
+    pub fn percent(value: FLOAT) -> String {
+        percent_with_digits(value, 0)
+    }
+
+    /// Format a ratio as a percentage with an amount of digits after the decimal point.
+    #[rhai_fn(name = "percent")]
+    pub fn percent_with_digits(value: FLOAT, digits: INT) -> String {
+        format!("{:.*}%", clamp_digits(digits), value * 100.0)
+    }
+
+    /// The ordinal form of a number, like `1st`, `2nd`, `3rd` or `11th`.
+    pub fn ordinal(value: INT) -> String {
+        let suffix = match (value.abs() % 10, value.abs() % 100) {
+            (_, 11..=13) => "th",
+            (1, _) => "st",
+            (2, _) => "nd",
+            (3, _) => "rd",
+            _ => "th",
+        };
+
+        format!("{value}{suffix}")
+    }
+
+    /// The form of a word for a count, adding an `s` unless the count is one.
+    pub fn plural(count: INT, singular: ImmutableString) -> ImmutableString {
+        match count.abs() {
+            1 => singular,
+            _ => format!("{singular}s").into(),
+        }
+    }
+
+    /// The form of a word for a count, either its singular or plural form.
+    #[rhai_fn(name = "plural")]
+    pub fn plural_with(
+        count: INT,
+        singular: ImmutableString,
+        plural: ImmutableString,
+    ) -> ImmutableString {
+        match count.abs() {
+            1 => singular,
+            _ => plural,
+        }
+    }
+}
+
+#[export_module]
+mod text_filters {
+    use rhai::{Array, INT};
+
+    type StringResult = Result<String, Box<EvalAltResult>>;
+
+    /// Pad a value with spaces on the left, up to a width.
+    #[rhai_fn(return_raw)]
+    pub fn pad_left(ctx: NativeCallContext, value: Dynamic, width: INT) -> StringResult {
+        pad(&ctx, value, width, ' ', true)
+    }
+
+    /// Pad a value with a character on the left, up to a width.
+    #[rhai_fn(name = "pad_left", return_raw)]
+    pub fn pad_left_with(
+        ctx: NativeCallContext,
+        value: Dynamic,
+        width: INT,
+        fill: char,
+    ) -> StringResult {
+        pad(&ctx, value, width, fill, true)
+    }
+
+    /// Pad a value with spaces on the right, up to a width.
+    #[rhai_fn(return_raw)]
+    pub fn pad_right(ctx: NativeCallContext, value: Dynamic, width: INT) -> StringResult {
+        pad(&ctx, value, width, ' ', false)
+    }
+
+    /// Pad a value with a character on the right, up to a width.
+    #[rhai_fn(name = "pad_right", return_raw)]
+    pub fn pad_right_with(
+        ctx: NativeCallContext,
+        value: Dynamic,
+        width: INT,
+        fill: char,
+    ) -> StringResult {
+        pad(&ctx, value, width, fill, false)
+    }
+
+    /// Join the elements of an array with commas, like `a, b, c`.
+    #[rhai_fn(return_raw)]
+    pub fn join(ctx: NativeCallContext, array: &mut Array) -> StringResult {
+        join_with(ctx, array, ", ")
+    }
+
+    /// Join the elements of an array with a separator.
+    #[rhai_fn(name = "join", return_raw)]
+    pub fn join_with(ctx: NativeCallContext, array: &mut Array, separator: &str) -> StringResult {
+        let items: Vec<_> = array.iter().map(Dynamic::to_string).collect();
+
+        let length = items.iter().fold(0_usize, |length, item| {
+            length.saturating_add(item.len() + separator.len())
+        });
+        check_string_size(&ctx, length.saturating_sub(separator.len()))?;
+
+        Ok(items.join(separator))
+    }
+
+    pub fn upper(text: &str) -> String {
+        text.to_uppercase()
+    }
+
+    pub fn lower(text: &str) -> String {
+        text.to_lowercase()
+    }
+
+    /// Capitalize the first letter of every word, like `Hello World`.
+    pub fn title(text: &str) -> String {
+        let mut titled = String::with_capacity(text.len());
+        let mut is_word_start = true;
+
+        for ch in text.chars() {
+            match is_word_start {
+                true => titled.extend(ch.to_uppercase()),
+                false => titled.push(ch),
+            }
+
+            is_word_start = ch.is_whitespace() || ch == '-';
+        }
+
+        titled
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn thousands() {
+        assert_eq!(format_thousands("1234567"), "1,234,567");
+        assert_eq!(format_thousands("-1234.5678"), "-1,234.5678");
+        assert_eq!(format_thousands("123"), "123");
+        assert_eq!(format_thousands("0.5"), "0.5");
+    }
+}
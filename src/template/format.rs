@@ -0,0 +1,236 @@
+//! Turning the values of interpolations like `@price` into text.
+//!
+//! # Examples
+//! ```
+//! use flashmark::template::{format::Formatter, rational::Rational};
+//!
+//! let mut formatter = Formatter::new();
+//! assert_eq!(formatter.format(&(0.1 + 0.2).into()), "0.3");
+//!
+//! let items: rhai::Array = vec![1.into(), 2.into()];
+//! assert_eq!(formatter.format(&items.into()), "1, 2");
+//!
+//! let cups = Rational::new(3, 2).unwrap().with_unit("cup");
+//! assert_eq!(formatter.format(&rhai::Dynamic::from(cups.clone())), "3/2 cup");
+//!
+//! formatter.set_mixed_fractions(true);
+//! assert_eq!(formatter.format(&rhai::Dynamic::from(cups)), "1 1/2 cup");
+//! ```
+
+use std::{any::TypeId, collections::HashMap, sync::Arc};
+
+use rhai::Dynamic;
+
+use super::rational::Rational;
+
+/// Formats a value of a custom type.
+pub type FormatFn = dyn Fn(&Dynamic, &Formatter) -> String + Send + Sync;
+
+/// Formats the values written into templates.
+///
+/// Floats are rounded, arrays are written as lists like `1, 2, 3`,
+/// object maps like `a: 1, b: 2`, and [`Rational`]s like `3/4 cup`.
+/// Every other value is written with its `Display` implementation,
+/// unless a formatter is registered for its type.
+#[derive(Clone)]
+pub struct Formatter {
+    float_precision: Option<usize>,
+    separator: String,
+    mixed_fractions: bool,
+    custom: HashMap<TypeId, Arc<FormatFn>>,
+}
+
+impl Formatter {
+    /// The most digits floats are written with after the decimal point by default,
+    /// which is few enough to hide the error of adding floats like `0.1 + 0.2`.
+    pub const MAX_FLOAT_DIGITS: usize = 10;
+
+    pub fn new() -> Self {
+        let mut formatter = Self {
+            float_precision: None,
+            separator: ", ".to_string(),
+            mixed_fractions: false,
+            custom: HashMap::new(),
+        };
+
+        formatter.register(format_rational);
+
+        formatter
+    }
+
+    /// Returns the amount of digits floats are written with after the decimal point,
+    /// if it's fixed.
+    pub fn float_precision(&self) -> Option<usize> {
+        self.float_precision
+    }
+
+    /// Write floats with a fixed amount of digits after the decimal point.
+    ///
+    /// By default, they're rounded to [`Formatter::MAX_FLOAT_DIGITS`],
+    /// then written without trailing zeros,
+    /// or in scientific notation if they're too small to round, like `1e-12`.
+    pub fn set_float_precision(&mut self, precision: Option<usize>) {
+        self.float_precision = precision;
+    }
+
+    /// Returns what's written between the items of arrays and object maps.
+    pub fn separator(&self) -> &str {
+        &self.separator
+    }
+
+    /// Choose what's written between the items of arrays and object maps,
+    /// which is `", "` by default.
+    pub fn set_separator(&mut self, separator: impl Into<String>) {
+        self.separator = separator.into();
+    }
+
+    /// Choose whether fractions larger than one are written as mixed numbers,
+    /// like `1 1/2` instead of `3/2`.
+    pub fn set_mixed_fractions(&mut self, mixed_fractions: bool) {
+        self.mixed_fractions = mixed_fractions;
+    }
+
+    /// Format values of type `T` with `format`,
+    /// replacing the formatter of that type if there already is one.
+    ///
+    /// # Examples
+    /// ```
+    /// use flashmark::template::format::Formatter;
+    ///
+    /// #[derive(Clone)]
+    /// struct Celsius(f64);
+    ///
+    /// let mut formatter = Formatter::new();
+    /// formatter.register(|value: &Celsius, _| format!("{} °C", value.0));
+    ///
+    /// assert_eq!(formatter.format(&rhai::Dynamic::from(Celsius(21.5))), "21.5 °C");
+    /// ```
+    pub fn register<T: rhai::Variant + Clone>(
+        &mut self,
+        format: impl Fn(&T, &Formatter) -> String + Send + Sync + 'static,
+    ) {
+        let format = move |value: &Dynamic, formatter: &Formatter| {
+            let value = value
+                .read_lock::<T>()
+                .expect("formatters are only called with values of their type");
+
+            format(&value, formatter)
+        };
+
+        self.custom.insert(TypeId::of::<T>(), Arc::new(format));
+    }
+
+    /// Format a value.
+    pub fn format(&self, value: &Dynamic) -> String {
+        if let Some(format) = self.custom.get(&value.type_id()) {
+            return format(value, self);
+        }
+
+        if let Ok(value) = value.as_float() {
+            return self.format_float(value);
+        }
+
+        if let Some(array) = value.read_lock::<rhai::Array>() {
+            let items: Vec<_> = array.iter().map(|item| self.format(item)).collect();
+            return items.join(&self.separator);
+        }
+
+        if let Some(map) = value.read_lock::<rhai::Map>() {
+            let entries: Vec<_> = map
+                .iter()
+                .map(|(key, value)| format!("{key}: {}", self.format(value)))
+                .collect();
+            return entries.join(&self.separator);
+        }
+
+        value.to_string()
+    }
+
+    fn format_float(&self, value: rhai::FLOAT) -> String {
+        if let Some(precision) = self.float_precision {
+            return format!("{value:.precision$}");
+        }
+
+        if !value.is_finite() {
+            return value.to_string();
+        }
+
+        let formatted = format!("{value:.*}", Self::MAX_FLOAT_DIGITS);
+        let formatted = formatted.trim_end_matches('0').trim_end_matches('.');
+
+        match formatted {
+            // too small to round without losing every digit, like `1e-12`
+            "0" | "-0" if value != 0.0 => format!("{value:e}"),
+            "-0" => "0".to_string(),
+            _ => formatted.to_string(),
+        }
+    }
+}
+
+impl Default for Formatter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn format_rational(value: &Rational, formatter: &Formatter) -> String {
+    let (whole, rest) = value.to_mixed();
+
+    if !formatter.mixed_fractions || whole == 0 || rest.numer() == 0 {
+        return value.to_string();
+    }
+
+    // the sign is written once, before the whole part
+    let rest = rest.to_string();
+    format!("{whole} {}", rest.trim_start_matches('-'))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn floats() {
+        let mut formatter = Formatter::new();
+        assert_eq!(formatter.format(&2.0.into()), "2");
+        assert_eq!(formatter.format(&(-0.0).into()), "0");
+        assert_eq!(formatter.format(&1e-12.into()), "1e-12");
+        assert_eq!(formatter.format(&(-2.5e-11).into()), "-2.5e-11");
+        assert_eq!(formatter.format(&1e-10.into()), "0.0000000001");
+        assert_eq!(formatter.format(&(1.0 / 3.0).into()), "0.3333333333");
+
+        formatter.set_float_precision(Some(2));
+        assert_eq!(formatter.format(&2.0.into()), "2.00");
+    }
+
+    #[test]
+    fn collections() {
+        let mut formatter = Formatter::new();
+
+        let nested: rhai::Array = vec![1.5.into(), "a".into(), vec![Dynamic::TRUE].into()];
+        assert_eq!(formatter.format(&nested.clone().into()), "1.5, a, true");
+
+        let mut map = rhai::Map::new();
+        map.insert("b".into(), 2.into());
+        map.insert("a".into(), 1.into());
+        assert_eq!(formatter.format(&map.into()), "a: 1, b: 2");
+
+        formatter.set_separator(" / ");
+        assert_eq!(formatter.format(&nested.into()), "1.5 / a / true");
+    }
+
+    #[test]
+    fn mixed_fractions() {
+        let mut formatter = Formatter::new();
+        formatter.set_mixed_fractions(true);
+
+        let format = |numer, denom| {
+            let value = Rational::new(numer, denom).unwrap();
+            formatter.format(&Dynamic::from(value))
+        };
+
+        assert_eq!(format(-7, 2), "-3 1/2");
+        assert_eq!(format(1, 2), "1/2");
+        assert_eq!(format(4, 2), "2");
+    }
+}
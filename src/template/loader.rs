@@ -0,0 +1,163 @@
+use std::{
+    collections::HashMap,
+    io,
+    path::{Component, Path, PathBuf},
+};
+
+/// Loads the templates included with `@include`.
+///
+/// Like a rhai [`ModuleResolver`](rhai::ModuleResolver), a loader first resolves
+/// the path written in the template into a name, then loads the template by that name.
+pub trait TemplateLoader {
+    /// Returns the name of the template at `path`,
+    /// included from the template named `from`, or from the document itself.
+    ///
+    /// Every path to the same template must resolve to the same name,
+    /// so templates including themselves can be detected.
+    fn resolve(&self, from: Option<&str>, path: &str) -> String {
+        let _ = from;
+        path.to_string()
+    }
+
+    /// Returns the source of the template with a resolved name.
+    fn load(&self, name: &str) -> io::Result<String>;
+}
+
+/// Loads templates from a map of names to sources.
+///
+/// # Examples
+/// ```
+/// use flashmark::template::{self, MemoryLoader};
+///
+/// let loader = MemoryLoader::new().with("greeting", "Hello, @name!");
+///
+/// let mut env = template::Environment::with_engine(template::new_engine());
+/// env.set_loader(loader);
+/// env.scope_mut().push("name", "World");
+///
+/// let output = template::render_with_environment(env, "@include \"greeting\"").unwrap();
+/// assert_eq!(output, "Hello, World!\n");
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct MemoryLoader {
+    templates: HashMap<String, String>,
+}
+
+/// Loads templates from files, relative to a root directory.
+///
+/// Paths in included templates are relative to the directory of that template.
+#[derive(Debug, Clone)]
+pub struct FileLoader {
+    root: PathBuf,
+}
+
+impl<L: TemplateLoader + ?Sized> TemplateLoader for std::sync::Arc<L> {
+    fn resolve(&self, from: Option<&str>, path: &str) -> String {
+        (**self).resolve(from, path)
+    }
+
+    fn load(&self, name: &str) -> io::Result<String> {
+        (**self).load(name)
+    }
+}
+
+impl MemoryLoader {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn insert(&mut self, name: impl Into<String>, source: impl Into<String>) {
+        self.templates.insert(name.into(), source.into());
+    }
+
+    pub fn with(mut self, name: impl Into<String>, source: impl Into<String>) -> Self {
+        self.insert(name, source);
+        self
+    }
+}
+
+impl<N: Into<String>, S: Into<String>> FromIterator<(N, S)> for MemoryLoader {
+    fn from_iter<I: IntoIterator<Item = (N, S)>>(iter: I) -> Self {
+        let mut loader = Self::new();
+
+        for (name, source) in iter {
+            loader.insert(name, source);
+        }
+
+        loader
+    }
+}
+
+impl TemplateLoader for MemoryLoader {
+    fn load(&self, name: &str) -> io::Result<String> {
+        self.templates
+            .get(name)
+            .cloned()
+            .ok_or_else(|| io::ErrorKind::NotFound.into())
+    }
+}
+
+impl FileLoader {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        Self { root: root.into() }
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+}
+
+impl TemplateLoader for FileLoader {
+    /// Resolves a path relative to the including template,
+    /// or to the root directory if it starts with `/`,
+    /// removing any `.` and `..` components.
+    fn resolve(&self, from: Option<&str>, path: &str) -> String {
+        let base = match from {
+            Some(from) => Path::new(from).parent().unwrap_or(Path::new("")),
+            None => Path::new(""),
+        };
+
+        let mut resolved = PathBuf::new();
+
+        for component in base.join(path).components() {
+            match component {
+                Component::RootDir | Component::Prefix(_) => resolved.clear(),
+                Component::CurDir => (),
+                Component::ParentDir => {
+                    resolved.pop();
+                }
+                component => resolved.push(component),
+            }
+        }
+
+        resolved.to_string_lossy().into_owned()
+    }
+
+    fn load(&self, name: &str) -> io::Result<String> {
+        std::fs::read_to_string(self.root.join(name))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn file_loader_resolve() {
+        let loader = FileLoader::new("decks");
+
+        assert_eq!(loader.resolve(None, "./common/units.md"), "common/units.md");
+        assert_eq!(
+            loader.resolve(Some("common/units.md"), "table.md"),
+            "common/table.md"
+        );
+        assert_eq!(
+            loader.resolve(Some("common/units.md"), "../formulas.md"),
+            "formulas.md"
+        );
+        assert_eq!(
+            loader.resolve(Some("common/units.md"), "/../../formulas.md"),
+            "formulas.md"
+        );
+    }
+}
@@ -1,13 +1,40 @@
+pub mod compiled;
 pub mod environment;
+pub mod error;
+pub mod escape;
+pub mod filters;
+pub mod format;
+pub mod loader;
 pub mod parse;
+pub mod random;
+pub mod rational;
 pub mod render;
+pub mod source;
 
-pub use environment::Environment;
+pub use compiled::CompiledTemplate;
+pub use environment::{Environment, Limits};
+pub use error::{Diagnostic, DiagnosticKind, Error};
+pub use format::Formatter;
+pub use loader::{FileLoader, MemoryLoader, TemplateLoader};
+pub use render::Output;
+pub use source::{Location, SourceLine, SourceMap, Span};
 
 use parse::*;
-use render::Render;
 
-struct ModuleResolver;
+/// Resolves the modules templates can `import`.
+///
+/// Modules are built once, when the resolver is created.
+struct ModuleResolver {
+    rand: rhai::Shared<rhai::Module>,
+}
+
+impl ModuleResolver {
+    fn new() -> Self {
+        Self {
+            rand: random::module().into(),
+        }
+    }
+}
 
 impl rhai::ModuleResolver for ModuleResolver {
     fn resolve(
@@ -16,11 +43,9 @@ impl rhai::ModuleResolver for ModuleResolver {
         _source: Option<&str>,
         path: &str,
         pos: rhai::Position,
-    ) -> Result<std::rc::Rc<rhai::Module>, Box<rhai::EvalAltResult>> {
-        use rhai::packages::Package;
-
+    ) -> Result<rhai::Shared<rhai::Module>, Box<rhai::EvalAltResult>> {
         match path {
-            "rand" => Ok(rhai_rand::RandomPackage::new().as_shared_module()),
+            "rand" => Ok(self.rand.clone()),
             _ => Err(rhai::EvalAltResult::ErrorModuleNotFound(path.into(), pos).into()),
         }
     }
@@ -28,35 +53,130 @@ impl rhai::ModuleResolver for ModuleResolver {
 
 pub fn new_engine() -> rhai::Engine {
     let mut engine = rhai::Engine::new();
-    engine.set_module_resolver(ModuleResolver);
+    engine.set_module_resolver(ModuleResolver::new());
+    engine.register_global_module(filters::module().into());
+    engine.register_global_module(rational::module().into());
+
+    engine
+}
+
+/// The most operations the scripts and expressions of an environment can run combined,
+/// with an engine created by [`new_safe_engine`].
+pub const MAX_TOTAL_OPERATIONS: u64 = 10_000_000;
+
+/// Create an engine for templates that aren't trusted, like decks shared by strangers.
+///
+/// Scripts are limited in how long they can run and how much memory they can use,
+/// and can't print anything. Going over a limit is reported as a
+/// [`DiagnosticKind::ResourceLimit`].
+///
+/// Besides the limit of each script or expression, every one evaluated in an environment
+/// can run at most [`MAX_TOTAL_OPERATIONS`] combined,
+/// so a loop can't multiply how long a template runs.
+///
+/// The functions added to rhai, like the filters and the `rand` module,
+/// check the size of the strings they build before allocating them,
+/// since rhai only checks what a function returns.
+pub fn new_safe_engine() -> rhai::Engine {
+    let mut engine = new_engine();
+
+    engine.on_progress(|operations| {
+        environment::limit_total_operations(operations, MAX_TOTAL_OPERATIONS)
+    });
 
     engine
+        .set_max_operations(1_000_000)
+        .set_max_call_levels(32)
+        .set_max_expr_depths(64, 32)
+        .set_max_string_size(1024 * 1024)
+        .set_max_array_size(10_000)
+        .set_max_map_size(10_000)
+        .set_max_variables(1_000)
+        .set_max_functions(256)
+        .set_max_modules(16);
+
+    engine.on_print(|_| ()).on_debug(|_, _, _| ());
+
+    // code in strings can't be checked ahead of time
+    engine.disable_symbol("eval");
+
+    engine
+}
+
+pub fn render(input: &str) -> Result<String, Error> {
+    render_lenient(input).into_result()
 }
 
-pub fn render(input: &str) -> String {
-    render_with_engine(new_engine(), input)
+pub fn render_with_engine(
+    engine: impl Into<rhai::Shared<rhai::Engine>>,
+    input: &str,
+) -> Result<String, Error> {
+    render_lenient_with_engine(engine, input).into_result()
 }
 
-pub fn render_with_engine(engine: rhai::Engine, input: &str) -> String {
-    let (front_matter, input) = parse::parse_front_matter(input);
+pub fn render_with_environment(env: Environment, input: &str) -> Result<String, Error> {
+    render_lenient_with_environment(env, input).into_result()
+}
+
+/// Render as much of the template as possible,
+/// collecting diagnostics instead of failing.
+pub fn render_lenient(input: &str) -> Output {
+    render_lenient_with_engine(new_engine(), input)
+}
+
+pub fn render_lenient_with_engine(
+    engine: impl Into<rhai::Shared<rhai::Engine>>,
+    input: &str,
+) -> Output {
+    render_document(Environment::with_engine(engine), input)
+}
 
-    let env = if let Some(script) = front_matter {
-        match Environment::try_with_script(engine, script) {
-            Ok(env) => env,
-            Err(err) => {
-                return format!("Error: {}", err);
-            }
+/// Render a whole document, running its front matter in the environment first.
+///
+/// Like the other lenient functions, diagnostics are collected instead of failing.
+pub fn render_document(mut env: Environment, input: &str) -> Output {
+    let (front_matter, mut lines) = split_document(input);
+
+    let mut output = Output::new();
+
+    if let Some(script) = front_matter {
+        if let Err(err) = env.run_script(script) {
+            output.report(Diagnostic::front_matter(FRONT_MATTER_LOCATION, err));
         }
-    } else {
-        Environment::with_engine(engine)
-    };
+    }
+
+    render_body(&mut env, &mut lines, &mut output);
 
-    render_with_environment(env, input)
+    output
 }
 
-pub fn render_with_environment(mut env: Environment, input: &str) -> String {
-    let mut output = String::new();
-    parse_root(&env, &mut input.lines()).render(&mut env, 0, &mut output);
+pub fn render_lenient_with_environment(mut env: Environment, input: &str) -> Output {
+    let mut output = Output::new();
+    render_body(&mut env, &mut SourceLine::lines(input, 1, 0), &mut output);
 
     output
 }
+
+/// Where the front matter script starts, right after the opening `---` line.
+const FRONT_MATTER_LOCATION: Location = Location { line: 2, column: 1 };
+
+/// Split a document into its front matter, and the lines of its body.
+fn split_document(input: &str) -> (Option<&str>, impl Iterator<Item = SourceLine<'_>>) {
+    let (front_matter, body) = parse::parse_front_matter(input);
+
+    let offset = input.len() - body.len();
+    let first_line = input[..offset].lines().count() + 1;
+
+    (front_matter, SourceLine::lines(body, first_line, offset))
+}
+
+fn render_body<'a>(
+    env: &mut Environment,
+    lines: &mut impl Iterator<Item = SourceLine<'a>>,
+    output: &mut Output,
+) {
+    let (root, diagnostics) = parse_root(env, lines);
+
+    output.diagnostics.extend(diagnostics);
+    root.render_root(env, output);
+}
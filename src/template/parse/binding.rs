@@ -0,0 +1,153 @@
+use std::{borrow::Cow, fmt};
+
+/// The names an item of a `@for` loop is bound to.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Pattern<'a> {
+    /// `item`
+    Name(Cow<'a, str>),
+    /// `[first, second]`, binding the elements of an array.
+    Array(Vec<Cow<'a, str>>),
+    /// `{key, other}`, binding the properties of an object map.
+    Map(Vec<Cow<'a, str>>),
+}
+
+/// Everything bound by a `@for` loop, like `item` or `(index, [first, second])`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Binding<'a> {
+    pub index: Option<Cow<'a, str>>,
+    pub item: Pattern<'a>,
+}
+
+fn parse_names(list: &str) -> Option<Vec<Cow<'_, str>>> {
+    list.split(',')
+        .map(str::trim)
+        .map(|name| rhai::is_valid_identifier(name).then_some(name.into()))
+        .collect()
+}
+
+impl<'a> Pattern<'a> {
+    pub fn parse(src: &'a str) -> Option<Self> {
+        let src = src.trim();
+
+        if let Some(list) = src.strip_prefix('[').and_then(|src| src.strip_suffix(']')) {
+            parse_names(list).map(Pattern::Array)
+        } else if let Some(list) = src.strip_prefix('{').and_then(|src| src.strip_suffix('}')) {
+            parse_names(list).map(Pattern::Map)
+        } else {
+            rhai::is_valid_identifier(src).then_some(Pattern::Name(src.into()))
+        }
+    }
+
+    pub fn into_owned(self) -> Pattern<'static> {
+        let into_owned = |names: Vec<Cow<'a, str>>| {
+            names
+                .into_iter()
+                .map(|name| name.into_owned().into())
+                .collect()
+        };
+
+        match self {
+            Pattern::Name(name) => Pattern::Name(name.into_owned().into()),
+            Pattern::Array(names) => Pattern::Array(into_owned(names)),
+            Pattern::Map(names) => Pattern::Map(into_owned(names)),
+        }
+    }
+
+    /// Bind a value to the names of the pattern,
+    /// returning every name along with its value.
+    ///
+    /// Returns the value back if it doesn't fit the pattern.
+    pub fn bind(&self, value: rhai::Dynamic) -> Result<Vec<(&str, rhai::Dynamic)>, rhai::Dynamic> {
+        match self {
+            Pattern::Name(name) => Ok(vec![(name, value)]),
+            Pattern::Array(names) => {
+                let array = match value.try_cast_result::<rhai::Array>() {
+                    Ok(array) if array.len() == names.len() => array,
+                    Ok(array) => return Err(array.into()),
+                    Err(value) => return Err(value),
+                };
+
+                Ok(names.iter().map(|name| &**name).zip(array).collect())
+            }
+            Pattern::Map(names) => {
+                let mut map = value.try_cast_result::<rhai::Map>()?;
+
+                if !names.iter().all(|name| map.contains_key(&**name)) {
+                    return Err(map.into());
+                }
+
+                let bound = names
+                    .iter()
+                    .map(|name| (&**name, map.remove(&**name).unwrap_or_default()))
+                    .collect();
+
+                Ok(bound)
+            }
+        }
+    }
+}
+
+impl<'a> Binding<'a> {
+    /// Parse the binding of a `@for` loop, which can be wrapped
+    /// in parentheses along with a name for the index.
+    ///
+    /// Returns `None` if any of the names is invalid.
+    pub fn parse(src: &'a str) -> Option<Self> {
+        let src = src.trim();
+
+        let Some(inner) = src.strip_prefix('(').and_then(|src| src.strip_suffix(')')) else {
+            return Pattern::parse(src).map(|item| Binding { index: None, item });
+        };
+
+        let (index, item) = inner.split_once(',')?;
+        let index = index.trim();
+
+        rhai::is_valid_identifier(index).then_some(())?;
+
+        Some(Binding {
+            index: Some(index.into()),
+            item: Pattern::parse(item)?,
+        })
+    }
+
+    pub fn into_owned(self) -> Binding<'static> {
+        Binding {
+            index: self.index.map(|index| index.into_owned().into()),
+            item: self.item.into_owned(),
+        }
+    }
+}
+
+impl fmt::Display for Pattern<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Pattern::Name(name) => write!(f, "{name}"),
+            Pattern::Array(names) => write!(f, "[{}]", names.join(", ")),
+            Pattern::Map(names) => write!(f, "{{{}}}", names.join(", ")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_binding() {
+        let binding = Binding::parse("(i, [key, value])").unwrap();
+        assert_eq!(binding.index.as_deref(), Some("i"));
+        assert_eq!(
+            binding.item,
+            Pattern::Array(vec!["key".into(), "value".into()])
+        );
+
+        let binding = Binding::parse(" { name , age } ").unwrap();
+        assert_eq!(binding.index, None);
+        assert_eq!(binding.item.to_string(), "{name, age}");
+
+        assert!(Binding::parse("x").is_some());
+        assert!(Binding::parse("(x)").is_none());
+        assert!(Binding::parse("[a, 1]").is_none());
+        assert!(Binding::parse("a b").is_none());
+    }
+}
@@ -1,31 +1,100 @@
+use super::{scan, Trim, TrimSide};
+use crate::template::SourceLine;
+
 #[derive(Clone, Copy)]
 pub struct Directive<'a> {
+    pub line: SourceLine<'a>,
     pub indent: usize,
     pub name: &'a str,
     pub args: Option<&'a str>,
+    /// The directive without its `@` and trim markers.
+    pub text: &'a str,
+    /// Set by `@-` at the start of the line, like `@-name`,
+    /// to trim the whitespace rendered before the directive.
+    pub trim_before: bool,
+    /// Set by `@-` at the end of the line, like `@name args @-`,
+    /// to trim the whitespace rendered after the directive.
+    pub trim_after: bool,
 }
 
 pub struct MissingAtSignError;
 
-impl<'a> TryFrom<&'a str> for Directive<'a> {
+impl<'a> TryFrom<SourceLine<'a>> for Directive<'a> {
     type Error = MissingAtSignError;
 
-    fn try_from(line: &'a str) -> Result<Self, Self::Error> {
-        let trimmed = line.trim_start();
+    fn try_from(line: SourceLine<'a>) -> Result<Self, Self::Error> {
+        let trimmed = line.text.trim_start();
         let rest = trimmed.strip_prefix('@').ok_or(MissingAtSignError)?;
 
-        let indent = line.len() - trimmed.len();
+        let indent = line.text.len() - trimmed.len();
+
+        // trim markers go at the edges of the line, like on lines of text
+        let (rest, trim_before) = match rest.strip_prefix('-') {
+            Some(rest) => (rest, true),
+            None => (rest, false),
+        };
+
+        let rest = strip_comment(rest).trim_end();
+        let (rest, trim_after) = match rest.strip_suffix("@-") {
+            Some(rest) if !rest.ends_with('\\') => (rest.trim_end(), true),
+            _ => (rest, false),
+        };
 
-        let Some((name, args)) = rest.split_once(' ') else {
-            return Ok(Directive {
-                indent,
-                name: rest,
-                args: None,
-            });
+        let (name, args) = match rest.split_once(' ') {
+            Some((name, args)) => (name, Some(args.trim())),
+            None => (rest, None),
         };
 
-        let args = Some(args.trim());
+        Ok(Directive {
+            line,
+            indent,
+            name,
+            args,
+            text: rest,
+            trim_before,
+            trim_after,
+        })
+    }
+}
+
+/// Returns `text` without the comment ending it, if there is one,
+/// like `@# note` or `@* note *@`.
+///
+/// A block comment is only left out if nothing but whitespace follows it.
+fn strip_comment(text: &str) -> &str {
+    let comment = scan::scan(text, |i, ch, _| {
+        ch == '@' && text[i + 1..].starts_with(['#', '*'])
+    });
+
+    let Ok(Some(start)) = comment else {
+        return text;
+    };
+
+    let is_comment = match text[start + 1..].strip_prefix('*') {
+        Some(block) => block
+            .split_once("*@")
+            .is_some_and(|(_, rest)| rest.trim().is_empty()),
+        None => true,
+    };
+
+    match is_comment {
+        true => &text[..start],
+        false => text,
+    }
+}
+
+impl Directive<'_> {
+    /// Returns the trim marker on a side of the directive, if it has one.
+    pub fn trim(&self, side: TrimSide) -> Option<Trim> {
+        let has_marker = match side {
+            TrimSide::Before => self.trim_before,
+            TrimSide::After => self.trim_after,
+        };
 
-        Ok(Directive { indent, name, args })
+        has_marker.then(|| Trim {
+            line: self.line.number,
+            span: self.line.span(),
+            side,
+        })
     }
 }
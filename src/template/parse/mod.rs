@@ -1,47 +1,316 @@
+mod binding;
 mod directive;
+mod scan;
 
+use std::{
+    borrow::Cow,
+    collections::{HashMap, HashSet},
+};
+
+pub use binding::{Binding, Pattern};
 use directive::Directive;
 
-use super::Environment;
+use super::{Diagnostic, DiagnosticKind, Environment, Location, SourceLine, Span};
+use crate::slides::Boundary;
 
 pub struct Block<'a> {
     pub indent: usize,
     pub nodes: Vec<Node<'a>>,
 }
 
+/// A compiled rhai expression, along with where it is in the template.
+pub struct Expression {
+    pub ast: rhai::AST,
+    pub location: Location,
+    pub span: Span,
+}
+
 pub struct IfBlock<'a> {
-    pub condition: rhai::AST,
+    pub condition: Expression,
     pub block: Block<'a>,
 }
 
 pub struct IfChainBlock<'a> {
+    /// The line number of the `@if` directive.
+    pub line: usize,
+    /// Spans from the `@if` directive to the `@end` directive.
+    pub span: Span,
     pub if_blocks: Vec<IfBlock<'a>>,
     pub else_block: Option<Block<'a>>,
 }
 
+/// A `@match` directive, with its `@case` arms.
+pub struct MatchBlock<'a> {
+    /// The line number of the `@match` directive.
+    pub line: usize,
+    /// Spans from the `@match` directive to the `@end` directive.
+    pub span: Span,
+    pub subject: Expression,
+    /// The conditions of the arms compare their patterns
+    /// to the subject, in the [`MatchBlock::SUBJECT`] variable.
+    pub cases: Vec<IfBlock<'a>>,
+    pub else_block: Option<Block<'a>>,
+}
+
 pub struct ForBlock<'a> {
-    pub binding: &'a str,
-    pub iterable: rhai::AST,
+    /// The line number of the `@for` directive.
+    pub line: usize,
+    /// Spans from the `@for` directive to the `@end` directive.
+    pub span: Span,
+    pub binding: Binding<'a>,
+    pub iterable: Expression,
+    pub block: Block<'a>,
+    /// Rendered instead if there's nothing to iterate over.
+    pub else_block: Option<Block<'a>>,
+}
+
+pub struct WhileBlock<'a> {
+    /// The line number of the `@while` directive.
+    pub line: usize,
+    /// Spans from the `@while` directive to the `@end` directive.
+    pub span: Span,
+    pub condition: Expression,
     pub block: Block<'a>,
 }
 
+/// A `@break` or `@continue` directive.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Control {
+    Break,
+    Continue,
+}
+
+pub struct ControlFlow {
+    pub location: Location,
+    pub span: Span,
+    pub control: Control,
+}
+
 pub struct Line<'a> {
-    pub front: &'a str,
-    pub expressions: Vec<(Result<rhai::AST, rhai::ParseError>, &'a str)>,
+    pub line: usize,
+    pub span: Span,
+    pub front: Cow<'a, str>,
+    /// Segments that failed to parse are `None`,
+    /// their diagnostics are reported while parsing.
+    pub segments: Segments<'a>,
+    /// Set by `@-` at the start of the line, to join it onto the previous one.
+    pub trim_before: bool,
+    /// Set by `@-` at the end of the line, to join the next one onto it.
+    pub trim_after: bool,
+    /// Set for the body of an inline `@if` or `@for`,
+    /// which is rendered as part of its line, without a line break.
+    pub is_inline: bool,
+}
+
+/// The segments of a line, each followed by the text up to the next one.
+pub type Segments<'a> = Vec<(Option<Segment<'a>>, Cow<'a, str>)>;
+
+/// A value written into a line, like `@name` or `@(price | currency)`.
+pub struct Interpolation {
+    pub value: Expression,
+    /// Each filter calls a function with the value so far,
+    /// in the [`Interpolation::VALUE`] variable.
+    pub filters: Vec<Expression>,
+    /// Set by `@!(expr)` and `@raw(expr)`, to write the value without escaping it.
+    pub is_raw: bool,
+}
+
+/// A part of a line starting with `@`.
+pub enum Segment<'a> {
+    Interpolation(Interpolation),
+    /// An inline `@if(condition){...}`, its blocks only holding an inline line.
+    If(IfChainBlock<'a>),
+    /// An inline `@for(item in items){...}`, its blocks only holding an inline line.
+    For(ForBlock<'a>),
+}
+
+/// A variable assignment, from a `@let` or `@set` directive.
+pub struct Assignment<'a> {
+    pub location: Location,
+    pub span: Span,
+    pub name: Cow<'a, str>,
+    pub value: Expression,
+    /// Whether a new variable is declared with `@let`,
+    /// instead of an existing one being changed with `@set`.
+    pub is_declaration: bool,
+}
+
+/// A template loaded by an `@include` directive.
+pub struct Include<'a> {
+    pub location: Location,
+    pub span: Span,
+    /// The resolved name of the included template.
+    pub name: Cow<'a, str>,
+    pub block: Block<'a>,
+}
+
+/// A macro defined with a `@macro` directive.
+pub struct Macro {
+    pub name: String,
+    pub params: Vec<String>,
+    pub block: Block<'static>,
+}
+
+/// An invocation of a macro, like `@name(args)` on its own line.
+pub struct Call<'a> {
+    pub location: Location,
+    pub span: Span,
+    /// The whitespace before the invocation, which every rendered line is indented by.
+    pub indentation: Cow<'a, str>,
+    /// Evaluates to an array of the arguments.
+    pub args: Expression,
+    pub definition: rhai::Shared<Macro>,
+}
+
+/// Which side of a directive a trim marker is on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TrimSide {
+    /// `@-` at the start of a line trims the whitespace rendered before,
+    /// joining what comes next onto the last line.
+    Before,
+    /// `@-` at the end of a line trims the whitespace rendered after,
+    /// skipping blank lines and indentation.
+    After,
+}
+
+/// A trim marker on a directive.
+pub struct Trim {
+    pub line: usize,
+    pub span: Span,
+    pub side: TrimSide,
+}
+
+/// A slide or card boundary, from a `@slide` or `@card` directive.
+pub struct Break {
+    pub location: Location,
+    pub span: Span,
+    pub boundary: Boundary,
 }
 
 pub enum Node<'a> {
     Line(Line<'a>),
     If(IfChainBlock<'a>),
+    Match(MatchBlock<'a>),
     For(ForBlock<'a>),
-    Err {
-        indent: usize,
-        error: rhai::ParseError,
-    },
+    While(WhileBlock<'a>),
+    Assign(Assignment<'a>),
+    Include(Include<'a>),
+    Call(Call<'a>),
+    Control(ControlFlow),
+    Trim(Trim),
+    Break(Break),
 }
 
-pub fn parse_root<'a>(env: &Environment, lines: &mut impl Iterator<Item = &'a str>) -> Block<'a> {
-    parse_block(env, lines, 0, |_| false).0
+/// State shared by every part of the parser.
+pub struct Context<'e> {
+    pub env: &'e Environment,
+    pub diagnostics: Vec<Diagnostic>,
+    /// The end of the last line consumed by the parser.
+    end: usize,
+    /// The names of the templates being included, from the outermost one.
+    includes: Vec<String>,
+    /// Every macro defined so far, including in included templates.
+    macros: HashMap<String, rhai::Shared<Macro>>,
+    /// How many `@for` loops the parser is in.
+    loop_depth: usize,
+    /// The trim marker after the last directive parsed,
+    /// until it's placed where the whitespace after the directive starts.
+    trim_after: Option<Trim>,
+    /// The names of the functions filters can call, collected the first time they're needed.
+    functions: Option<HashSet<String>>,
+}
+
+impl<'e> Context<'e> {
+    pub fn new(env: &'e Environment) -> Self {
+        Self {
+            env,
+            diagnostics: vec![],
+            end: 0,
+            includes: vec![],
+            macros: HashMap::new(),
+            loop_depth: 0,
+            trim_after: None,
+            functions: None,
+        }
+    }
+
+    /// Returns whether `filter` is a call to a function, like `name` or `name(args)`.
+    fn is_filter(&mut self, filter: &str) -> bool {
+        let Some(name) = filter_name(filter) else {
+            return false;
+        };
+
+        let env = self.env;
+        self.functions
+            .get_or_insert_with(|| env.function_names())
+            .contains(name)
+    }
+
+    /// Compile an expression, a subslice of `line`.
+    ///
+    /// Reports a diagnostic if it fails to compile.
+    fn compile_expr(&mut self, line: &SourceLine, script: &str) -> Option<Expression> {
+        self.compile_expr_at(line.location_of(script), line.span_of(script), script)
+    }
+
+    /// Compile a filter, a subslice of `line` like `name` or `name(args)`,
+    /// into a call with the value piped into it as the first argument.
+    ///
+    /// Reports a diagnostic if it fails to compile.
+    fn compile_filter(&mut self, line: &SourceLine, filter: &str) -> Option<Expression> {
+        let filter = filter.trim();
+        let location = line.location_of(filter);
+
+        let (name, args) = split_call(filter).unwrap_or((filter, ""));
+
+        // the arguments are checked on their own first, so their diagnostics are located
+        // where they are in the template, starting one column before them like an array
+        if !args.trim().is_empty() {
+            let mut args_location = line.location_of(args);
+            args_location.column -= 1;
+
+            self.compile_expr_at(args_location, line.span_of(args), &format!("[{args}]"))?;
+        }
+
+        let script = match args.trim() {
+            "" => format!("{name}({})", Interpolation::VALUE),
+            _ => format!("{name}({}, {args})", Interpolation::VALUE),
+        };
+
+        self.compile_expr_at(location, line.span_of(filter), &script)
+    }
+
+    /// Compile an expression that isn't part of the template source,
+    /// reporting diagnostics as if it started at `location`.
+    fn compile_expr_at(
+        &mut self,
+        location: Location,
+        span: Span,
+        script: &str,
+    ) -> Option<Expression> {
+        match self.env.compile_expr(script) {
+            Ok(ast) => Some(Expression {
+                ast,
+                location,
+                span,
+            }),
+            Err(err) => {
+                self.diagnostics.push(Diagnostic::parse(location, err));
+                None
+            }
+        }
+    }
+}
+
+/// Parse lines into a block, along with any diagnostics found while parsing.
+pub fn parse_root<'a>(
+    env: &Environment,
+    lines: &mut impl Iterator<Item = SourceLine<'a>>,
+) -> (Block<'a>, Vec<Diagnostic>) {
+    let mut cx = Context::new(env);
+    let (block, _) = parse_block(&mut cx, lines, 0, |_| false);
+
+    (block, cx.diagnostics)
 }
 
 pub fn parse_front_matter(input: &str) -> (Option<&str>, &str) {
@@ -57,8 +326,8 @@ fn is_end_directive(directive: &Directive) -> bool {
 }
 
 fn parse_block<'a>(
-    env: &Environment,
-    lines: &mut impl Iterator<Item = &'a str>,
+    cx: &mut Context,
+    lines: &mut impl Iterator<Item = SourceLine<'a>>,
     indent: usize,
     mut is_sentinel: impl FnMut(&Directive) -> bool,
 ) -> (Block<'a>, Option<Directive<'a>>) {
@@ -67,26 +336,43 @@ fn parse_block<'a>(
         nodes: vec![],
     };
 
+    // the directive opening the block can trim the whitespace at its start
+    if let Some(trim) = cx.trim_after.take() {
+        block.nodes.push(Node::Trim(trim));
+    }
+
     while let Some(line) = lines.next() {
+        cx.end = line.span().end;
+
         if let Ok(directive) = Directive::try_from(line) {
             if is_sentinel(&directive) {
+                block
+                    .nodes
+                    .extend(directive.trim(TrimSide::Before).map(Node::Trim));
+                cx.trim_after = directive.trim(TrimSide::After);
+
                 return (block, Some(directive));
             }
 
-            if let Some(res) = parse_directive_block(env, directive, lines) {
-                let node = res.unwrap_or_else(|err| Node::Err {
-                    indent: directive.indent,
-                    error: err,
-                });
+            // blocks take the marker at their start, and leave the one of their `@end`
+            cx.trim_after = directive.trim(TrimSide::After);
 
-                block.nodes.push(node);
+            if let Some(res) = parse_directive_block(cx, directive, lines) {
+                block
+                    .nodes
+                    .extend(directive.trim(TrimSide::Before).map(Node::Trim));
+                block.nodes.extend(res);
+                block.nodes.extend(cx.trim_after.take().map(Node::Trim));
 
                 continue;
             }
+
+            cx.trim_after = None;
         }
 
-        let line = parse_line(env, line);
-        block.nodes.push(Node::Line(line));
+        block
+            .nodes
+            .extend(parse_line(cx, line, lines).map(Node::Line));
     }
 
     (block, None)
@@ -106,106 +392,782 @@ fn split_expr_prefix(line: &str) -> Option<(&str, &str)> {
     })
 }
 
-fn split_expr(mut line: &str) -> (&str, &str) {
-    let mut paren_count = 0;
-    while let Some(next) = line.strip_prefix('(') {
-        paren_count += 1;
-        line = next;
+/// Splits an expression off the start of `line`, following an `@`,
+/// returning it along with the text after it.
+///
+/// Fails if it's in parentheses which aren't closed.
+fn split_expr(line: &str) -> Result<(&str, &str), scan::Unterminated> {
+    match line.starts_with('(') {
+        true => scan::split_group(line),
+        false => Ok(split_bare_expr(line)),
     }
+}
 
-    let mut end = 0;
-    let mut streak = 0;
-    for ch in line.chars() {
-        if paren_count == 0 {
-            // if there was no opening paren, we only want to match a single identifier
-            if !ch.is_alphabetic() {
-                break;
+/// Splits an expression without parentheses around it off the start of `text`,
+/// like `name`, `user.name`, `xs[0]` or `card.front.len()`.
+///
+/// Punctuation that can't continue the expression is left out,
+/// like the period ending a sentence in `Hello, @name.`
+fn split_bare_expr(text: &str) -> (&str, &str) {
+    let mut end = identifier_len(text);
+
+    while end > 0 {
+        let rest = &text[end..];
+
+        let len = if let Some(member) = rest.strip_prefix('.').or(rest.strip_prefix("?.")) {
+            match identifier_len(member) {
+                0 => break,
+                len => rest.len() - member.len() + len,
             }
-        } else if ch == ')' {
-            streak += 1;
-        } else if streak >= paren_count {
-            break;
+        } else if let Some((_, after)) = rest
+            .starts_with(['[', '('])
+            .then(|| scan::split_group(rest).ok())
+            .flatten()
+        {
+            rest.len() - after.len()
         } else {
-            streak = 0;
-        }
+            break;
+        };
 
-        end += ch.len_utf8();
+        end += len;
     }
 
-    if streak >= paren_count {
-        (&line[..(end - paren_count)], &line[end..])
-    } else {
-        (line, "")
+    text.split_at(end)
+}
+
+/// Returns the length of the identifier at the start of `text`, or zero if there isn't one.
+fn identifier_len(text: &str) -> usize {
+    if !text.starts_with(|ch: char| ch.is_alphabetic() || ch == '_') {
+        return 0;
     }
+
+    text.find(|ch: char| !ch.is_alphanumeric() && ch != '_')
+        .unwrap_or(text.len())
 }
 
-fn parse_line<'a>(env: &Environment, line: &'a str) -> Line<'a> {
-    let Some((front, mut rest)) = split_expr_prefix(line) else {
-        return Line {
-            front: line,
-            expressions: vec![],
+/// Splits a line into its indentation and the rest of it without trim markers,
+/// along with whether it starts and ends with one.
+fn split_trim_markers(text: &str) -> (&str, &str, bool, bool) {
+    let body = text.trim_start();
+    let indentation = &text[..text.len() - body.len()];
+
+    let (body, trim_before) = match body.strip_prefix("@-") {
+        Some(body) => (body, true),
+        None => (body, false),
+    };
+
+    let (body, trim_after) = match body.strip_suffix("@-") {
+        Some(body) if !body.ends_with('\\') => (body, true),
+        _ => (body, false),
+    };
+
+    (indentation, body, trim_before, trim_after)
+}
+
+/// Returns `None` if the line only holds comments.
+fn parse_line<'a>(
+    cx: &mut Context,
+    line: SourceLine<'a>,
+    lines: &mut impl Iterator<Item = SourceLine<'a>>,
+) -> Option<Line<'a>> {
+    let (indentation, body, trim_before, trim_after) = split_trim_markers(line.text);
+
+    let mut parts = parse_segments(cx, &line, body);
+    let mut span = line.span();
+
+    // a block comment continues on the next lines, until one of them closes it
+    while let Some(location) = parts.open_comment.take() {
+        let Some(next) = lines.next() else {
+            let kind = DiagnosticKind::UnterminatedComment;
+            cx.diagnostics.push(Diagnostic::new(location, kind));
+            break;
         };
+
+        cx.end = next.span().end;
+        span = span.join(next.span());
+
+        match next.text.split_once("*@") {
+            Some((_, rest)) => parts.extend(parse_segments(cx, &next, rest)),
+            None => parts.open_comment = Some(location),
+        }
+    }
+
+    let is_comment = parts.front.trim().is_empty() && parts.segments.is_empty();
+    if parts.has_comment && is_comment {
+        return None;
+    }
+
+    // the indentation is kept, so the line is unindented like the ones around it
+    let front = match parts.front {
+        Cow::Borrowed(front) if !trim_before => line.text[..indentation.len() + front.len()].into(),
+        front => format!("{indentation}{front}").into(),
+    };
+
+    Some(Line {
+        line: line.number,
+        span,
+        front,
+        segments: parts.segments,
+        trim_before,
+        trim_after,
+        is_inline: false,
+    })
+}
+
+/// Parses the body of an inline block into a line of its own.
+fn parse_inline_line<'a>(cx: &mut Context, line: &SourceLine<'a>, body: &'a str) -> Line<'a> {
+    let parts = parse_segments(cx, line, body);
+
+    // the body ends with the block, so a comment can't continue on the next line
+    if let Some(location) = parts.open_comment {
+        let kind = DiagnosticKind::UnterminatedComment;
+        cx.diagnostics.push(Diagnostic::new(location, kind));
+    }
+
+    Line {
+        line: line.number,
+        span: line.span_of(body),
+        front: parts.front,
+        segments: parts.segments,
+        trim_before: false,
+        trim_after: false,
+        is_inline: true,
+    }
+}
+
+/// The parts of a line, without its comments.
+struct Parts<'a> {
+    /// The text before the first segment.
+    ///
+    /// It's borrowed as long as it's a prefix of the parsed text.
+    front: Cow<'a, str>,
+    segments: Segments<'a>,
+    has_comment: bool,
+    /// Where a block comment that isn't closed by the end of the line starts.
+    open_comment: Option<Location>,
+}
+
+impl<'a> Parts<'a> {
+    /// The text after the last segment, or before the first one.
+    fn last_text(&mut self) -> &mut Cow<'a, str> {
+        match self.segments.last_mut() {
+            Some((_, text)) => text,
+            None => &mut self.front,
+        }
+    }
+
+    /// Append text to the end, after a comment.
+    fn push_str(&mut self, text: &str) {
+        if !text.is_empty() {
+            self.last_text().to_mut().push_str(text);
+        }
+    }
+
+    /// Append the parts after a block comment spanning several lines.
+    fn extend(&mut self, parts: Parts<'a>) {
+        self.push_str(&parts.front);
+        self.segments.extend(parts.segments);
+        self.has_comment |= parts.has_comment;
+        self.open_comment = parts.open_comment;
+    }
+}
+
+/// Splits `text`, a subslice of `line`, into the text before the first `@`
+/// and every segment after it, each followed by its text.
+///
+/// Comments are left out, `@#` until the end of the line, and `@*` until `*@`.
+fn parse_segments<'a>(cx: &mut Context, line: &SourceLine<'a>, text: &'a str) -> Parts<'a> {
+    let (front, mut rest) = split_expr_prefix(text).unwrap_or((text, ""));
+
+    let mut parts = Parts {
+        front: front.into(),
+        segments: vec![],
+        has_comment: false,
+        open_comment: None,
     };
 
-    let mut expressions = vec![];
     while !rest.is_empty() {
-        let (expr, text) = split_expr(rest);
-        let expr = env.compile_expr(expr);
+        if rest.starts_with('#') {
+            parts.has_comment = true;
+
+            // trailing whitespace would be a hard line break in markdown
+            match parts.last_text() {
+                Cow::Borrowed(text) => *text = text.trim_end(),
+                Cow::Owned(text) => text.truncate(text.trim_end().len()),
+            }
+
+            break;
+        }
+
+        if let Some(comment) = rest.strip_prefix('*') {
+            parts.has_comment = true;
+
+            let Some((_, text)) = comment.split_once("*@") else {
+                // starting at the `@`
+                let mut location = line.location_of(rest);
+                location.column -= 1;
+
+                parts.open_comment = Some(location);
+                break;
+            };
+
+            let (text, tail) = split_expr_prefix(text).unwrap_or((text, ""));
+            rest = tail;
+
+            parts.push_str(text);
+            continue;
+        }
+
+        let (segment, text) = match parse_inline_block(cx, line, rest) {
+            Some(parsed) => parsed,
+            None => parse_expr_segment(cx, line, rest),
+        };
 
         let (text, tail) = split_expr_prefix(text).unwrap_or((text, ""));
         rest = tail;
 
-        expressions.push((expr, text));
+        parts.segments.push((segment, text.into()));
+    }
+
+    parts
+}
+
+/// Parses an expression at the start of `text`, following an `@`,
+/// returning it along with the text after it.
+fn parse_expr_segment<'a>(
+    cx: &mut Context,
+    line: &SourceLine<'a>,
+    text: &'a str,
+) -> (Option<Segment<'a>>, &'a str) {
+    let raw = ["!", "raw"].into_iter().find_map(|prefix| {
+        let rest = text.strip_prefix(prefix)?;
+        rest.starts_with('(').then_some(rest)
+    });
+
+    let text = raw.unwrap_or(text);
+    let (expr, text) = match split_expr(text) {
+        Ok(split) => split,
+        Err(unterminated) => {
+            let location = line.location_of(&text[unterminated.offset..]);
+            let kind = DiagnosticKind::UnterminatedExpression(unterminated.expected);
+            cx.diagnostics.push(Diagnostic::new(location, kind));
+
+            // the rest of the line is part of the expression
+            return (None, "");
+        }
+    };
+
+    let mut pipeline = split_pipeline(expr, |filter| cx.is_filter(filter)).into_iter();
+    let value = pipeline
+        .next()
+        .and_then(|value| cx.compile_expr(line, value));
+
+    // every filter is compiled, to report all of their diagnostics
+    let filters: Option<Vec<_>> = pipeline
+        .map(|filter| cx.compile_filter(line, filter))
+        .collect::<Vec<_>>()
+        .into_iter()
+        .collect();
+
+    let interpolation = value.zip(filters).map(|(value, filters)| Interpolation {
+        value,
+        filters,
+        is_raw: raw.is_some(),
+    });
+
+    (interpolation.map(Segment::Interpolation), text)
+}
+
+/// Splits an expression on the pipes between filters, like `price | round(2) | currency`.
+///
+/// The filters are the pipes at the end of the expression followed by a call to
+/// a function, like `name` or `name(args)`, that `is_filter` returns `true` for.
+/// Every other pipe is a bitwise or, like in `flags | 2`,
+/// as are the ones inside of brackets, strings or comments.
+fn split_pipeline(expr: &str, mut is_filter: impl FnMut(&str) -> bool) -> Vec<&str> {
+    let mut pipes = vec![];
+
+    // the expression was already scanned, so it can't be unterminated
+    let _ = scan::scan(expr, |i, ch, depth| {
+        if ch != '|' || depth > 0 {
+            return false;
+        }
+
+        let is_operator = expr[..i].ends_with('|') || expr[i + 1..].starts_with(['|', '=']);
+
+        if !is_operator {
+            pipes.push(i);
+        }
+
+        false
+    });
+
+    let mut parts = vec![];
+    let mut end = expr.len();
+
+    for i in pipes.into_iter().rev() {
+        let filter = &expr[i + 1..end];
+
+        if !is_filter(filter) {
+            break;
+        }
+
+        parts.push(filter);
+        end = i;
+    }
+
+    parts.push(&expr[..end]);
+    parts.reverse();
+    parts
+}
+
+/// Returns the name of the function a filter calls,
+/// if it's shaped like `name` or `name(args)`.
+fn filter_name(filter: &str) -> Option<&str> {
+    let filter = filter.trim();
+    let (name, rest) = filter.split_at(identifier_len(filter));
+
+    if !rhai::is_valid_function_name(name) {
+        return None;
+    }
+
+    match rest.trim_start() {
+        "" => Some(name),
+        rest if rest.starts_with('(') => {
+            let (_, rest) = scan::split_group(rest).ok()?;
+            rest.trim().is_empty().then_some(name)
+        }
+        _ => None,
+    }
+}
+
+/// Splits the body of an inline block, like `{text @(value)}`, off the start of `text`,
+/// returning what's between its braces and the rest.
+///
+/// Expressions in the body are skipped whole, so they can hold braces like `@("}")`.
+/// Returns `None` if it doesn't start with `{`, or it's never closed.
+fn split_body(text: &str) -> Option<(&str, &str)> {
+    let inner = text.strip_prefix('{')?;
+
+    let mut depth = 1;
+    let mut rest = inner;
+    while let Some(i) = rest.find(['\\', '@', '{', '}']) {
+        let after = &rest[i + 1..];
+
+        rest = match &rest[i..i + 1] {
+            "\\" => {
+                let mut escaped = after.chars();
+                escaped.next();
+                escaped.as_str()
+            }
+            "@" => skip_expr(after),
+            "{" => {
+                depth += 1;
+                after
+            }
+            _ => {
+                depth -= 1;
+
+                if depth == 0 {
+                    let end = inner.len() - rest.len() + i;
+                    return Some((&inner[..end], after));
+                }
+
+                after
+            }
+        };
+    }
+
+    None
+}
+
+/// Skips the expression at the start of `text`, following an `@`,
+/// including the header of an inline block.
+///
+/// An unterminated expression isn't skipped, leaving it to be reported where it's parsed.
+fn skip_expr(text: &str) -> &str {
+    let group = ["!", "raw", "if", "for", ""]
+        .into_iter()
+        .find_map(|prefix| {
+            text.strip_prefix(prefix)
+                .filter(|rest| rest.starts_with('('))
+        });
+
+    match group {
+        Some(group) => scan::split_group(group).map_or(text, |(_, rest)| rest),
+        None => split_bare_expr(text).1,
+    }
+}
+
+/// Parses an inline `@if(condition){...}` or `@for(item in items){...}`,
+/// both followed by an optional `else{...}`.
+///
+/// Returns `None` if `text`, following an `@`, doesn't start with either of them,
+/// or the segment along with the text after it, `None` if it failed to parse.
+fn parse_inline_block<'a>(
+    cx: &mut Context,
+    line: &SourceLine<'a>,
+    text: &'a str,
+) -> Option<(Option<Segment<'a>>, &'a str)> {
+    let (keyword, rest) = ["if", "for"].into_iter().find_map(|keyword| {
+        let rest = text.strip_prefix(keyword)?;
+        rest.starts_with('(').then_some((keyword, rest))
+    })?;
+
+    // the rest of the line can't be parsed once a delimiter is missing
+    let missing = |cx: &mut Context, at: &str, token: &str, description: &str| {
+        let kind = rhai::ParseErrorType::MissingToken(token.into(), description.into());
+        let location = line.location_of(at);
+        cx.diagnostics
+            .push(Diagnostic::new(location, DiagnosticKind::Parse(kind)));
+
+        Some((None, ""))
+    };
+
+    let Ok((header, rest)) = scan::split_group(rest) else {
+        return missing(
+            cx,
+            rest,
+            ")",
+            &format!("to end the header of the inline @{keyword}"),
+        );
+    };
+
+    let Some((body, rest)) = split_body(rest.trim_start()) else {
+        return missing(
+            cx,
+            rest,
+            "{",
+            &format!("to start the body of the inline @{keyword}"),
+        );
+    };
+
+    let (else_body, rest) = match rest
+        .trim_start()
+        .strip_prefix("else")
+        .map(str::trim_start)
+        .filter(|after| after.starts_with('{'))
+    {
+        Some(after) => match split_body(after) {
+            Some((else_body, rest)) => (Some(else_body), rest),
+            None => return missing(cx, after, "}", "to end the body of the inline @else"),
+        },
+        None => (None, rest),
+    };
+
+    let mut inline_block = |body| Block {
+        indent: 0,
+        nodes: vec![Node::Line(parse_inline_line(cx, line, body))],
+    };
+
+    let block = inline_block(body);
+    let else_block = else_body.map(inline_block);
+
+    let span = Span::new(line.span_of(text).start, line.span_of(rest).start);
+
+    let segment = match keyword {
+        "if" => cx.compile_expr(line, header).map(|condition| {
+            Segment::If(IfChainBlock {
+                line: line.number,
+                span,
+                if_blocks: vec![IfBlock { condition, block }],
+                else_block,
+            })
+        }),
+        _ => {
+            let Some((binding_src, iterable_src)) = header.split_once(" in ") else {
+                return missing(cx, header, "in", "after the variable of the inline @for");
+            };
+
+            let (binding, iterable) = parse_for_header(cx, line, binding_src, iterable_src);
+
+            iterable.zip(binding).map(|(iterable, binding)| {
+                Segment::For(ForBlock {
+                    line: line.number,
+                    span,
+                    binding,
+                    iterable,
+                    block,
+                    else_block,
+                })
+            })
+        }
+    };
+
+    Some((segment, rest))
+}
+
+/// Parses the binding and iterable of a `@for` loop,
+/// reporting a diagnostic for each of them that's invalid.
+fn parse_for_header<'a>(
+    cx: &mut Context,
+    line: &SourceLine<'a>,
+    binding_src: &'a str,
+    iterable_src: &str,
+) -> (Option<Binding<'a>>, Option<Expression>) {
+    let binding = Binding::parse(binding_src);
+    let iterable = cx.compile_expr(line, iterable_src);
+
+    if binding.is_none() {
+        let location = line.location_of(binding_src);
+        let kind = DiagnosticKind::Parse(rhai::ParseErrorType::VariableExpected);
+        cx.diagnostics.push(Diagnostic::new(location, kind));
     }
 
-    Line { front, expressions }
+    (binding, iterable)
 }
 
+/// Returns `None` if the directive doesn't start a block,
+/// or `Some(None)` if it does but failed to parse.
 fn parse_directive_block<'a>(
-    env: &Environment,
+    cx: &mut Context,
     directive: Directive<'a>,
-    lines: &mut impl Iterator<Item = &'a str>,
-) -> Option<Result<Node<'a>, rhai::ParseError>> {
+    lines: &mut impl Iterator<Item = SourceLine<'a>>,
+) -> Option<Option<Node<'a>>> {
     match (directive.name, directive.args) {
-        ("if", Some(condition)) => {
-            let res = parse_if_chain(env, condition, lines, directive.indent).map(Node::If);
-            Some(res)
+        ("if", Some(_)) => {
+            let if_chain = parse_if_chain(cx, directive, lines).map(Node::If);
+            Some(if_chain)
+        }
+        ("match", Some(subject)) => {
+            Some(parse_match(cx, directive, subject, lines).map(Node::Match))
         }
         ("for", Some(header)) => {
-            let (binding, iterable) = header.split_once(" in ")?;
-            let binding = binding.trim();
-            let iterable = match env.compile_expr(iterable) {
-                Ok(iterable) => iterable,
-                Err(err) => return Some(Err(err)),
-            };
+            let (binding_src, iterable_src) = header.split_once(" in ")?;
+            let (binding, iterable) =
+                parse_for_header(cx, &directive.line, binding_src, iterable_src);
+
+            fn is_sentinel(directive: &Directive<'_>) -> bool {
+                matches!(
+                    (directive.name, directive.args.is_none()),
+                    ("else", true) | ("end", true)
+                )
+            }
+
+            cx.loop_depth += 1;
+            let (block, closing_directive) = parse_block(cx, lines, directive.indent, is_sentinel);
+            cx.loop_depth -= 1;
 
-            let (block, _) = parse_block(env, lines, directive.indent, is_end_directive);
-            let for_block = ForBlock {
+            let else_block = closing_directive
+                .filter(|closing| closing.name == "else")
+                .map(|_| parse_block(cx, lines, directive.indent, is_end_directive).0);
+
+            let for_block = iterable.zip(binding).map(|(iterable, binding)| ForBlock {
+                line: directive.line.number,
+                span: Span::new(directive.line.offset, cx.end),
                 binding,
                 iterable,
                 block,
+                else_block,
+            });
+
+            Some(for_block.map(Node::For))
+        }
+        ("while", Some(condition)) => {
+            let condition = cx.compile_expr(&directive.line, condition);
+
+            cx.loop_depth += 1;
+            let (block, _) = parse_block(cx, lines, directive.indent, is_end_directive);
+            cx.loop_depth -= 1;
+
+            let while_block = condition.map(|condition| WhileBlock {
+                line: directive.line.number,
+                span: Span::new(directive.line.offset, cx.end),
+                condition,
+                block,
+            });
+
+            Some(while_block.map(Node::While))
+        }
+        ("break", None) | ("continue", None) => {
+            let text = directive.line.text.trim_start();
+            let location = directive.line.location_of(text);
+
+            if cx.loop_depth == 0 {
+                let kind = DiagnosticKind::OutsideLoop(directive.name.to_string());
+                cx.diagnostics.push(Diagnostic::new(location, kind));
+                return Some(None);
+            }
+
+            let control = match directive.name {
+                "break" => Control::Break,
+                _ => Control::Continue,
             };
 
-            Some(Ok(Node::For(for_block)))
+            Some(Some(Node::Control(ControlFlow {
+                location,
+                span: directive.line.span(),
+                control,
+            })))
         }
-        _ => None,
+        ("let", Some(assignment)) | ("set", Some(assignment)) => {
+            let location = directive.line.location_of(directive.line.text.trim_start());
+
+            let parsed = assignment
+                .split_once('=')
+                .map(|(name, value)| (name.trim(), value))
+                .filter(|(name, _)| rhai::is_valid_identifier(name));
+
+            let Some((name, value_src)) = parsed else {
+                let kind = DiagnosticKind::Parse(rhai::ParseErrorType::VariableExpected);
+                cx.diagnostics.push(Diagnostic::new(location, kind));
+                return Some(None);
+            };
+
+            let assignment = cx
+                .compile_expr(&directive.line, value_src)
+                .map(|value| Assignment {
+                    location: directive.line.location_of(name),
+                    span: directive.line.span(),
+                    name: name.into(),
+                    value,
+                    is_declaration: directive.name == "let",
+                });
+
+            Some(assignment.map(Node::Assign))
+        }
+        ("include", Some(path)) => Some(parse_include(cx, directive, path).map(Node::Include)),
+        ("macro", Some(header)) => {
+            parse_macro(cx, directive, header, lines);
+            Some(None)
+        }
+        ("slide", None) | ("card", None) => {
+            let boundary = match directive.name {
+                "slide" => Boundary::Slide,
+                _ => Boundary::Card,
+            };
+
+            let text = directive.line.text;
+            let slide_break = Break {
+                location: directive.line.location_of(text.trim_start()),
+                span: directive.line.span(),
+                boundary,
+            };
+
+            Some(Some(Node::Break(slide_break)))
+        }
+        _ => parse_call(cx, directive).map(|call| call.map(Node::Call)),
     }
 }
 
+/// The names of every directive, which can't be used as macro names.
+///
+/// A line holding only `@` and one of them is always a directive,
+/// so a variable with one of these names is written like `@(card)`.
+const DIRECTIVES: &[&str] = &[
+    "if", "elif", "else", "end", "match", "case", "for", "while", "break", "continue", "let",
+    "set", "include", "macro", "slide", "card",
+];
+
+/// Splits `name(args)` into the name and the arguments.
+fn split_call(text: &str) -> Option<(&str, &str)> {
+    let (name, args) = text.split_once('(')?;
+    let args = args.strip_suffix(')')?;
+
+    Some((name.trim_end(), args))
+}
+
+/// Parses a macro definition, making it available to every line after it.
+fn parse_macro<'a>(
+    cx: &mut Context,
+    directive: Directive<'a>,
+    header: &str,
+    lines: &mut impl Iterator<Item = SourceLine<'a>>,
+) {
+    let location = directive.line.location_of(header);
+
+    let definition = split_call(header).and_then(|(name, params)| {
+        let params: Vec<_> = match params.trim() {
+            "" => vec![],
+            params => params.split(',').map(str::trim).collect(),
+        };
+
+        let is_valid = rhai::is_valid_identifier(name)
+            && params.iter().all(|param| rhai::is_valid_identifier(param))
+            && params
+                .iter()
+                .enumerate()
+                .all(|(i, param)| !params[..i].contains(param));
+
+        is_valid.then_some((name, params))
+    });
+
+    // the body is parsed even if the definition is invalid, to report its diagnostics,
+    // and outside of any loop, since a macro can be invoked anywhere
+    let loop_depth = std::mem::take(&mut cx.loop_depth);
+    let (block, _) = parse_block(cx, lines, directive.indent, is_end_directive);
+    cx.loop_depth = loop_depth;
+
+    let Some((name, params)) = definition else {
+        let kind = DiagnosticKind::MacroDefinition;
+        cx.diagnostics.push(Diagnostic::new(location, kind));
+        return;
+    };
+
+    if DIRECTIVES.contains(&name) {
+        let kind = DiagnosticKind::Parse(rhai::ParseErrorType::Reserved(name.to_string()));
+        cx.diagnostics.push(Diagnostic::new(location, kind));
+        return;
+    }
+
+    let definition = Macro {
+        name: name.to_string(),
+        params: params.into_iter().map(String::from).collect(),
+        block: block.into_owned(),
+    };
+
+    cx.macros
+        .insert(name.to_string(), rhai::Shared::new(definition));
+}
+
+/// Returns `None` if the line isn't an invocation of a defined macro,
+/// or `Some(None)` if it is but failed to parse.
+fn parse_call<'a>(cx: &mut Context, directive: Directive<'a>) -> Option<Option<Call<'a>>> {
+    let line = directive.line;
+
+    let (name, args) = split_call(directive.text)?;
+    let definition = cx.macros.get(name)?.clone();
+
+    // the arguments are evaluated as a single array,
+    // starting one column before them to make room for the opening bracket
+    let mut location = line.location_of(args);
+    location.column -= 1;
+
+    let args = cx.compile_expr_at(location, line.span_of(args), &format!("[{args}]"));
+
+    let call = args.map(|args| Call {
+        location: line.location_of(line.text.trim_start()),
+        span: line.span(),
+        indentation: line.text[..directive.indent].into(),
+        args,
+        definition,
+    });
+
+    Some(call)
+}
+
 fn parse_if_chain<'a>(
-    env: &Environment,
-    condition: &str,
-    lines: &mut impl Iterator<Item = &'a str>,
-    indent: usize,
-) -> Result<IfChainBlock<'a>, rhai::ParseError> {
+    cx: &mut Context,
+    directive: Directive<'a>,
+    lines: &mut impl Iterator<Item = SourceLine<'a>>,
+) -> Option<IfChainBlock<'a>> {
+    let indent = directive.indent;
+
     let mut if_chain = IfChainBlock {
+        line: directive.line.number,
+        span: directive.line.span(),
         if_blocks: vec![],
         else_block: None,
     };
 
-    let mut cond_src = condition;
+    // a single condition failing to compile poisons the whole chain,
+    // but the rest of it is still parsed to report every diagnostic
+    let mut is_valid = true;
+
+    let mut cond_directive = directive;
     loop {
-        let condition = env.compile_expr(cond_src)?;
+        let cond_src = cond_directive.args.unwrap_or_default();
+        let condition = cx.compile_expr(&cond_directive.line, cond_src);
 
         fn is_sentinel(directive: &Directive<'_>) -> bool {
             matches!(
@@ -214,28 +1176,189 @@ fn parse_if_chain<'a>(
             )
         }
 
-        let (block, closing_directive) = parse_block(env, lines, indent, is_sentinel);
+        let (block, closing_directive) = parse_block(cx, lines, indent, is_sentinel);
 
-        if_chain.if_blocks.push(IfBlock { condition, block });
+        match condition {
+            Some(condition) => if_chain.if_blocks.push(IfBlock { condition, block }),
+            None => is_valid = false,
+        }
 
-        match closing_directive
-            .map(|directive| (directive.name, directive.args))
-            .unwrap_or(("end", None))
-        {
-            ("elif", Some(cond)) => cond_src = cond,
-            ("else", _) => break,
-            _ => return Ok(if_chain),
+        match closing_directive {
+            Some(directive) if directive.name == "elif" => cond_directive = directive,
+            Some(directive) if directive.name == "else" => break,
+            _ => {
+                if_chain.span.end = cx.end;
+                return is_valid.then_some(if_chain);
+            }
         }
     }
 
-    let (block, _) = parse_block(env, lines, indent, is_end_directive);
+    let (block, _) = parse_block(cx, lines, indent, is_end_directive);
 
     if_chain.else_block = Some(block);
+    if_chain.span.end = cx.end;
+
+    is_valid.then_some(if_chain)
+}
+
+fn parse_include<'a>(
+    cx: &mut Context,
+    directive: Directive<'a>,
+    path: &str,
+) -> Option<Include<'a>> {
+    let location = directive.line.location_of(path);
+
+    let report = |cx: &mut Context, kind| {
+        cx.diagnostics.push(Diagnostic::new(location, kind));
+        None
+    };
+
+    let Some(path) = path
+        .strip_prefix('"')
+        .and_then(|path| path.strip_suffix('"'))
+    else {
+        return report(cx, DiagnosticKind::IncludePath);
+    };
+
+    let Some(loader) = cx.env.loader() else {
+        return report(cx, DiagnosticKind::NoLoader);
+    };
+
+    let name = loader.resolve(cx.includes.last().map(String::as_str), path);
+
+    if cx.includes.contains(&name) {
+        return report(cx, DiagnosticKind::IncludeCycle(name));
+    }
+
+    let source = match loader.load(&name) {
+        Ok(source) => source,
+        Err(err) => return report(cx, DiagnosticKind::Load(name, err)),
+    };
+
+    // indent the included lines like the directive,
+    // so they're unindented along with the lines around it
+    let indentation = &directive.line.text[..directive.indent];
+    let source: String = source
+        .lines()
+        .map(|line| match line.trim().is_empty() {
+            true => format!("{line}\n"),
+            false => format!("{indentation}{line}\n"),
+        })
+        .collect();
+
+    let end = cx.end;
+    let first_diagnostic = cx.diagnostics.len();
+    cx.includes.push(name);
+
+    // the marker of the directive trims after the included template, not inside of it
+    let trim_after = cx.trim_after.take();
+
+    let mut lines = SourceLine::lines(&source, 1, 0);
+    let (block, _) = parse_block(cx, &mut lines, directive.indent, |_| false);
+
+    cx.trim_after = trim_after;
+
+    let name = cx.includes.pop().expect("the include was pushed");
+    cx.end = end;
+
+    let include = Include {
+        location,
+        span: directive.line.span(),
+        name: name.into(),
+        block: block.into_owned(),
+    };
+
+    let diagnostics = cx.diagnostics.split_off(first_diagnostic);
+    cx.diagnostics.extend(
+        diagnostics
+            .into_iter()
+            .map(|diagnostic| include.wrap(diagnostic)),
+    );
+
+    Some(include)
+}
+
+fn parse_match<'a>(
+    cx: &mut Context,
+    directive: Directive<'a>,
+    subject: &str,
+    lines: &mut impl Iterator<Item = SourceLine<'a>>,
+) -> Option<MatchBlock<'a>> {
+    let indent = directive.indent;
+    let subject = cx.compile_expr(&directive.line, subject);
+
+    let mut is_valid = subject.is_some();
+    let mut cases = vec![];
+    let mut else_block = None;
+
+    fn is_sentinel(directive: &Directive<'_>) -> bool {
+        matches!(
+            (directive.name, directive.args.is_none()),
+            ("case", false) | ("else", true) | ("end", true)
+        )
+    }
+
+    // only blank lines are allowed before the first arm
+    let (block, mut closing_directive) = parse_block(cx, lines, indent, is_sentinel);
+
+    if let Some(node) = block.nodes.iter().find(|node| node.indentation().is_some()) {
+        let location = Location::new(node.line(), 1);
+        cx.diagnostics
+            .push(Diagnostic::new(location, DiagnosticKind::ExpectedCase));
+    }
+
+    while let Some(case_directive) = closing_directive.take() {
+        match case_directive.name {
+            "case" => {
+                let patterns = case_directive.args.unwrap_or_default();
+
+                // the patterns are compared as a single array,
+                // starting one column before them to make room for the opening bracket
+                let mut location = case_directive.line.location_of(patterns);
+                location.column -= 1;
+
+                let span = case_directive.line.span_of(patterns);
+                let script = format!("[{patterns}].contains({})", MatchBlock::SUBJECT);
+                let condition = cx.compile_expr_at(location, span, &script);
+
+                let (block, closing) = parse_block(cx, lines, indent, is_sentinel);
+
+                match condition {
+                    Some(condition) => cases.push(IfBlock { condition, block }),
+                    None => is_valid = false,
+                }
 
-    Ok(if_chain)
+                closing_directive = closing;
+            }
+            "else" => {
+                let (block, _) = parse_block(cx, lines, indent, is_end_directive);
+                else_block = Some(block);
+            }
+            _ => (),
+        }
+    }
+
+    let match_block = MatchBlock {
+        line: directive.line.number,
+        span: Span::new(directive.line.offset, cx.end),
+        subject: subject?,
+        cases,
+        else_block,
+    };
+
+    is_valid.then_some(match_block)
 }
 
 impl<'a> Block<'a> {
+    /// Copy every borrowed part of the block,
+    /// so it can outlive the template source.
+    pub fn into_owned(self) -> Block<'static> {
+        Block {
+            indent: self.indent,
+            nodes: self.nodes.into_iter().map(Node::into_owned).collect(),
+        }
+    }
+
     pub fn min_indentation(&self) -> usize {
         self.nodes
             .iter()
@@ -246,6 +1369,22 @@ impl<'a> Block<'a> {
 }
 
 impl<'a> IfChainBlock<'a> {
+    pub fn into_owned(self) -> IfChainBlock<'static> {
+        IfChainBlock {
+            line: self.line,
+            span: self.span,
+            if_blocks: self
+                .if_blocks
+                .into_iter()
+                .map(|if_block| IfBlock {
+                    condition: if_block.condition,
+                    block: if_block.block.into_owned(),
+                })
+                .collect(),
+            else_block: self.else_block.map(Block::into_owned),
+        }
+    }
+
     pub fn min_indentation(&self) -> Option<usize> {
         self.if_blocks
             .iter()
@@ -254,14 +1393,11 @@ impl<'a> IfChainBlock<'a> {
             .min()
     }
 
-    pub fn get_branch(
-        &self,
-        env: &mut Environment,
-    ) -> Option<Result<&Block<'a>, Box<rhai::EvalAltResult>>> {
+    pub fn get_branch(&self, env: &mut Environment) -> Option<Result<&Block<'a>, Diagnostic>> {
         for block in self.if_blocks.iter() {
-            match env.eval_ast::<bool>(&block.condition) {
+            match env.eval_ast::<bool>(&block.condition.ast) {
                 Ok(true) => return Some(Ok(&block.block)),
-                Err(err) => return Some(Err(err)),
+                Err(err) => return Some(Err(Diagnostic::eval(block.condition.location, err))),
                 Ok(false) => (),
             }
         }
@@ -270,22 +1406,242 @@ impl<'a> IfChainBlock<'a> {
     }
 }
 
+impl<'a> MatchBlock<'a> {
+    /// The variable holding the subject while the arms are compared to it.
+    pub const SUBJECT: &'static str = "__match_subject";
+
+    pub fn into_owned(self) -> MatchBlock<'static> {
+        MatchBlock {
+            line: self.line,
+            span: self.span,
+            subject: self.subject,
+            cases: self
+                .cases
+                .into_iter()
+                .map(|case| IfBlock {
+                    condition: case.condition,
+                    block: case.block.into_owned(),
+                })
+                .collect(),
+            else_block: self.else_block.map(Block::into_owned),
+        }
+    }
+
+    pub fn min_indentation(&self) -> Option<usize> {
+        self.cases
+            .iter()
+            .map(|case| case.block.indent)
+            .chain(self.else_block.as_ref().map(|block| block.indent))
+            .min()
+    }
+
+    /// Returns the block of the first arm matching the subject.
+    ///
+    /// The subject is only evaluated once.
+    pub fn get_branch(&self, env: &mut Environment) -> Option<Result<&Block<'a>, Diagnostic>> {
+        let subject = match env.eval_ast::<rhai::Dynamic>(&self.subject.ast) {
+            Ok(subject) => subject,
+            Err(err) => return Some(Err(Diagnostic::eval(self.subject.location, err))),
+        };
+
+        env.scope_mut().push_dynamic(Self::SUBJECT, subject);
+
+        let mut branch = self.else_block.as_ref().map(Ok);
+
+        for case in self.cases.iter() {
+            match env.eval_ast::<bool>(&case.condition.ast) {
+                Ok(true) => branch = Some(Ok(&case.block)),
+                Err(err) => branch = Some(Err(Diagnostic::eval(case.condition.location, err))),
+                Ok(false) => continue,
+            }
+
+            break;
+        }
+
+        env.scope_mut().pop();
+
+        branch
+    }
+}
+
+impl<'a> ForBlock<'a> {
+    /// The variable describing the current iteration.
+    ///
+    /// It can't be called `loop`, since that's a keyword in rhai.
+    pub const LOOP: &'static str = "forloop";
+
+    pub fn into_owned(self) -> ForBlock<'static> {
+        ForBlock {
+            line: self.line,
+            span: self.span,
+            binding: self.binding.into_owned(),
+            iterable: self.iterable,
+            block: self.block.into_owned(),
+            else_block: self.else_block.map(Block::into_owned),
+        }
+    }
+}
+
+impl<'a> Assignment<'a> {
+    pub fn into_owned(self) -> Assignment<'static> {
+        Assignment {
+            location: self.location,
+            span: self.span,
+            name: self.name.into_owned().into(),
+            value: self.value,
+            is_declaration: self.is_declaration,
+        }
+    }
+}
+
+impl<'a> WhileBlock<'a> {
+    pub fn into_owned(self) -> WhileBlock<'static> {
+        WhileBlock {
+            line: self.line,
+            span: self.span,
+            condition: self.condition,
+            block: self.block.into_owned(),
+        }
+    }
+}
+
+impl<'a> Include<'a> {
+    pub fn into_owned(self) -> Include<'static> {
+        Include {
+            location: self.location,
+            span: self.span,
+            name: self.name.into_owned().into(),
+            block: self.block.into_owned(),
+        }
+    }
+
+    /// Locate a diagnostic from the included template at the directive.
+    pub fn wrap(&self, mut diagnostic: Diagnostic) -> Diagnostic {
+        // undo the indentation added to the included lines
+        if diagnostic.location.column > self.block.indent {
+            diagnostic.location.column -= self.block.indent;
+        }
+
+        let kind = DiagnosticKind::Included(self.name.to_string(), Box::new(diagnostic));
+        Diagnostic::new(self.location, kind)
+    }
+}
+
+impl<'a> Call<'a> {
+    pub fn into_owned(self) -> Call<'static> {
+        Call {
+            location: self.location,
+            span: self.span,
+            indentation: self.indentation.into_owned().into(),
+            args: self.args,
+            definition: self.definition,
+        }
+    }
+}
+
 impl<'a> Line<'a> {
+    pub fn into_owned(self) -> Line<'static> {
+        Line {
+            line: self.line,
+            span: self.span,
+            front: self.front.into_owned().into(),
+            segments: self
+                .segments
+                .into_iter()
+                .map(|(segment, text)| (segment.map(Segment::into_owned), text.into_owned().into()))
+                .collect(),
+            trim_before: self.trim_before,
+            trim_after: self.trim_after,
+            is_inline: self.is_inline,
+        }
+    }
+
     pub fn indentation(&self) -> Option<usize> {
         let trimmed = self.front.trim_start();
 
-        (!trimmed.is_empty() || !self.expressions.is_empty())
+        (!trimmed.is_empty() || !self.segments.is_empty())
             .then_some(self.front.len() - trimmed.len())
     }
 }
 
+impl Interpolation {
+    /// The variable holding the value piped into a filter.
+    pub const VALUE: &'static str = "__filter_value";
+}
+
+impl<'a> Segment<'a> {
+    pub fn into_owned(self) -> Segment<'static> {
+        match self {
+            Segment::Interpolation(interpolation) => Segment::Interpolation(interpolation),
+            Segment::If(if_block) => Segment::If(if_block.into_owned()),
+            Segment::For(for_block) => Segment::For(for_block.into_owned()),
+        }
+    }
+}
+
 impl<'a> Node<'a> {
+    pub fn into_owned(self) -> Node<'static> {
+        match self {
+            Node::Line(line) => Node::Line(line.into_owned()),
+            Node::If(if_block) => Node::If(if_block.into_owned()),
+            Node::Match(match_block) => Node::Match(match_block.into_owned()),
+            Node::For(for_block) => Node::For(for_block.into_owned()),
+            Node::While(while_block) => Node::While(while_block.into_owned()),
+            Node::Assign(assignment) => Node::Assign(assignment.into_owned()),
+            Node::Include(include) => Node::Include(include.into_owned()),
+            Node::Call(call) => Node::Call(call.into_owned()),
+            Node::Control(control) => Node::Control(control),
+            Node::Trim(trim) => Node::Trim(trim),
+            Node::Break(slide_break) => Node::Break(slide_break),
+        }
+    }
+
+    /// Returns the line number the node starts at.
+    pub fn line(&self) -> usize {
+        match self {
+            Node::Line(line) => line.line,
+            Node::If(if_block) => if_block.line,
+            Node::Match(match_block) => match_block.line,
+            Node::For(for_block) => for_block.line,
+            Node::While(while_block) => while_block.line,
+            Node::Assign(assignment) => assignment.location.line,
+            Node::Include(include) => include.location.line,
+            Node::Call(call) => call.location.line,
+            Node::Control(control) => control.location.line,
+            Node::Trim(trim) => trim.line,
+            Node::Break(slide_break) => slide_break.location.line,
+        }
+    }
+
+    /// Returns the span of the node in the template source.
+    pub fn span(&self) -> Span {
+        match self {
+            Node::Line(line) => line.span,
+            Node::If(if_block) => if_block.span,
+            Node::Match(match_block) => match_block.span,
+            Node::For(for_block) => for_block.span,
+            Node::While(while_block) => while_block.span,
+            Node::Assign(assignment) => assignment.span,
+            Node::Include(include) => include.span,
+            Node::Call(call) => call.span,
+            Node::Control(control) => control.span,
+            Node::Trim(trim) => trim.span,
+            Node::Break(slide_break) => slide_break.span,
+        }
+    }
+
     pub fn indentation(&self) -> Option<usize> {
         match self {
             Node::Line(line) => line.indentation(),
             Node::If(if_block) => if_block.min_indentation(),
+            Node::Match(match_block) => match_block.min_indentation(),
             Node::For(for_block) => Some(for_block.block.indent),
-            Node::Err { indent, .. } => Some(*indent),
+            Node::While(while_block) => Some(while_block.block.indent),
+            Node::Include(include) => Some(include.block.indent),
+            Node::Call(call) => Some(call.indentation.len()),
+            // none of these are indented in the output,
+            // so they shouldn't affect the indentation
+            Node::Assign(_) | Node::Control(_) | Node::Trim(_) | Node::Break(_) => None,
         }
     }
 }
@@ -294,13 +1650,63 @@ impl<'a> Node<'a> {
 mod tests {
     use super::*;
 
+    mod spans {
+        use super::*;
+
+        fn parse(input: &str) -> Block<'_> {
+            let env = Environment::with_engine(rhai::Engine::new());
+            let (block, diagnostics) = parse_root(&env, &mut SourceLine::lines(input, 1, 0));
+            assert!(diagnostics.is_empty());
+
+            block
+        }
+
+        #[test]
+        fn lines() {
+            let input = "a\nbc @x\n";
+            let block = parse(input);
+
+            let spans: Vec<_> = block.nodes.iter().map(Node::span).collect();
+            assert_eq!(spans, [Span::new(0, 1), Span::new(2, 7)]);
+
+            let Node::Line(line) = &block.nodes[1] else {
+                panic!("expected a line");
+            };
+            let Some(Segment::Interpolation(interpolation)) = &line.segments[0].0 else {
+                panic!("expected an interpolation");
+            };
+            let expr = &interpolation.value;
+            assert_eq!(&input[expr.span.start..expr.span.end], "x");
+            assert_eq!(expr.location, Location::new(2, 5));
+        }
+
+        #[test]
+        fn blocks() {
+            let input = "a\n@if true\n  b\n@end\nc";
+            let block = parse(input);
+
+            let node = &block.nodes[1];
+            assert_eq!(node.line(), 2);
+            assert_eq!(
+                &input[node.span().start..node.span().end],
+                "@if true\n  b\n@end"
+            );
+            assert_eq!(block.nodes[2].line(), 5);
+        }
+    }
+
     mod node_indentation {
         use super::*;
 
         fn new_line(s: &str) -> Node<'_> {
             Node::Line(Line {
-                front: s,
-                expressions: Vec::new(),
+                line: 1,
+                span: Span::new(0, s.len()),
+                front: s.into(),
+                segments: Vec::new(),
+                trim_before: false,
+                trim_after: false,
+                is_inline: false,
             })
         }
 
@@ -316,4 +1722,40 @@ mod tests {
             assert_eq!(node.indentation(), None);
         }
     }
+
+    #[test]
+    fn bare_expressions() {
+        assert_eq!(
+            split_bare_expr("item_count items"),
+            ("item_count", " items")
+        );
+        assert_eq!(split_bare_expr("x2."), ("x2", "."));
+        assert_eq!(split_bare_expr("user.name."), ("user.name", "."));
+        assert_eq!(split_bare_expr("user?.name"), ("user?.name", ""));
+        assert_eq!(split_bare_expr("xs[i + 1][0]!"), ("xs[i + 1][0]", "!"));
+        assert_eq!(
+            split_bare_expr("card.front.len() ok"),
+            ("card.front.len()", " ok")
+        );
+        assert_eq!(split_bare_expr("name (aside)"), ("name", " (aside)"));
+        assert_eq!(split_bare_expr("xs[0"), ("xs", "[0"));
+        assert_eq!(split_bare_expr("2nd"), ("", "2nd"));
+    }
+
+    #[test]
+    fn pipelines() {
+        let split = |expr| split_pipeline(expr, |filter| filter_name(filter).is_some());
+
+        assert_eq!(split("x | f | g(1)"), ["x ", " f ", " g(1)"]);
+        assert_eq!(split("a || b |= c"), ["a || b |= c"]);
+        assert_eq!(split(r#"(a | f) | g("|")"#), ["(a | f) ", r#" g("|")"#]);
+        assert_eq!(split("café | f"), ["café ", " f"]);
+        assert_eq!(split("1 | 2 | f"), ["1 | 2 ", " f"]);
+        assert_eq!(split("a | f | 2"), ["a | f | 2"]);
+        assert_eq!(split("a | f(1) + 2"), ["a | f(1) + 2"]);
+        assert_eq!(split("a | if"), ["a | if"]);
+
+        let registered = |expr| split_pipeline(expr, |filter| filter.trim() == "f");
+        assert_eq!(registered("a | b | f"), ["a | b ", " f"]);
+    }
 }
@@ -0,0 +1,298 @@
+//! Scans rhai code embedded in a template, like the expression of `@(...)`,
+//! to find where it ends without being fooled by brackets in strings or comments.
+
+/// Something left open at the end of the scanned code, like a bracket or a string.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Unterminated {
+    /// Where it was opened, as a byte offset into the scanned code.
+    pub offset: usize,
+    /// What was expected to close it.
+    pub expected: &'static str,
+}
+
+/// Something the scanner is inside of.
+enum Frame {
+    /// A bracket, along with the one closing it.
+    Bracket(usize, char),
+    /// A backtick string, which can hold interpolations.
+    Template(usize),
+    /// An interpolation like `${value}` in a backtick string.
+    Interpolation(usize),
+}
+
+impl Frame {
+    fn unterminated(&self) -> Unterminated {
+        match *self {
+            Frame::Bracket(offset, close) => Unterminated {
+                offset,
+                expected: match close {
+                    ')' => ")",
+                    ']' => "]",
+                    _ => "}",
+                },
+            },
+            Frame::Template(offset) => Unterminated {
+                offset,
+                expected: "`",
+            },
+            Frame::Interpolation(offset) => Unterminated {
+                offset,
+                expected: "}",
+            },
+        }
+    }
+}
+
+type Chars<'a> = std::iter::Peekable<std::str::CharIndices<'a>>;
+
+/// Calls `visit` with every character of `code` outside of string literals,
+/// character literals and comments, along with how deeply it's nested in brackets.
+///
+/// Brackets are nested as deeply as what's around them, so a closing bracket without
+/// an opening one is visited at a depth of zero.
+/// Scanning stops at the first character `visit` returns `true` for, returning its offset.
+///
+/// Fails if a bracket is closed by the wrong kind of bracket,
+/// or anything is still open at the end of the code,
+/// returning the innermost thing that wasn't closed.
+pub fn scan(
+    code: &str,
+    mut visit: impl FnMut(usize, char, usize) -> bool,
+) -> Result<Option<usize>, Unterminated> {
+    let mut frames = vec![];
+    let mut chars = code.char_indices().peekable();
+
+    while let Some((i, ch)) = chars.next() {
+        if let Some(Frame::Template(_)) = frames.last() {
+            match ch {
+                '`' => _ = frames.pop(),
+                '$' if chars.next_if(|&(_, ch)| ch == '{').is_some() => {
+                    frames.push(Frame::Interpolation(i));
+                }
+                _ => (),
+            }
+
+            continue;
+        }
+
+        match ch {
+            '"' | '\'' => {
+                if !skip_quoted(&mut chars, ch) {
+                    let expected = if ch == '"' { "\"" } else { "'" };
+                    return Err(Unterminated {
+                        offset: i,
+                        expected,
+                    });
+                }
+
+                continue;
+            }
+            '`' => {
+                frames.push(Frame::Template(i));
+                continue;
+            }
+            '/' if chars.next_if(|&(_, ch)| ch == '/').is_some() => {
+                // a line comment ends with the line, which is usually the end of the code
+                while chars.next_if(|&(_, ch)| ch != '\n').is_some() {}
+                continue;
+            }
+            '/' if chars.next_if(|&(_, ch)| ch == '*').is_some() => {
+                if !skip_block_comment(&mut chars) {
+                    return Err(Unterminated {
+                        offset: i,
+                        expected: "*/",
+                    });
+                }
+
+                continue;
+            }
+            '(' | '[' | '{' => {
+                if visit(i, ch, frames.len()) {
+                    return Ok(Some(i));
+                }
+
+                let close = match ch {
+                    '(' => ')',
+                    '[' => ']',
+                    _ => '}',
+                };
+
+                frames.push(Frame::Bracket(i, close));
+                continue;
+            }
+            ')' | ']' | '}' => match frames.last() {
+                Some(&Frame::Bracket(_, close)) if close == ch => _ = frames.pop(),
+                Some(Frame::Interpolation(_)) if ch == '}' => {
+                    // back inside of the backtick string
+                    frames.pop();
+                    continue;
+                }
+                Some(frame) => return Err(frame.unterminated()),
+                None => (),
+            },
+            _ => (),
+        }
+
+        if visit(i, ch, frames.len()) {
+            return Ok(Some(i));
+        }
+    }
+
+    match frames.last() {
+        Some(frame) => Err(frame.unterminated()),
+        None => Ok(None),
+    }
+}
+
+/// Splits `code` after the bracket closing the one it starts with,
+/// returning what's between them and the rest.
+///
+/// # Panics
+/// Panics if `code` doesn't start with an opening bracket.
+pub fn split_group(code: &str) -> Result<(&str, &str), Unterminated> {
+    assert!(
+        code.starts_with(['(', '[', '{']),
+        "code should start with a bracket"
+    );
+
+    // the closing bracket is the first character back at the depth of the opening one
+    match scan(code, |i, _, depth| i > 0 && depth == 0)? {
+        Some(end) => Ok((&code[1..end], &code[end + 1..])),
+        None => unreachable!("unclosed brackets are unterminated"),
+    }
+}
+
+/// Skips to the end of a string or character literal opened by `quote`,
+/// returning whether it's closed.
+fn skip_quoted(chars: &mut Chars, quote: char) -> bool {
+    while let Some((_, ch)) = chars.next() {
+        match ch {
+            '\\' => _ = chars.next(),
+            _ if ch == quote => return true,
+            _ => (),
+        }
+    }
+
+    false
+}
+
+/// Skips to the end of a block comment, which can be nested like in rhai,
+/// returning whether it's closed.
+fn skip_block_comment(chars: &mut Chars) -> bool {
+    let mut depth = 1;
+
+    while let Some((_, ch)) = chars.next() {
+        match ch {
+            '/' if chars.next_if(|&(_, ch)| ch == '*').is_some() => depth += 1,
+            '*' if chars.next_if(|&(_, ch)| ch == '/').is_some() => {
+                depth -= 1;
+
+                if depth == 0 {
+                    return true;
+                }
+            }
+            _ => (),
+        }
+    }
+
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn unterminated(offset: usize, expected: &'static str) -> Unterminated {
+        Unterminated { offset, expected }
+    }
+
+    #[test]
+    fn groups() {
+        assert_eq!(split_group("(a) rest"), Ok(("a", " rest")));
+        assert_eq!(split_group("((a) + (b))!"), Ok(("(a) + (b)", "!")));
+        assert_eq!(
+            split_group("(f(g(x), [1, 2]))"),
+            Ok(("f(g(x), [1, 2])", ""))
+        );
+        assert_eq!(split_group("(#{ a: [1] }.a)."), Ok(("#{ a: [1] }.a", ".")));
+        assert_eq!(split_group("[0])"), Ok(("0", ")")));
+        assert_eq!(split_group("()"), Ok(("", "")));
+    }
+
+    #[test]
+    fn strings() {
+        assert_eq!(split_group(r#"("a)b") c"#), Ok((r#""a)b""#, " c")));
+        assert_eq!(
+            split_group(r#"("a\")" + ")")"#),
+            Ok((r#""a\")" + ")""#, ""))
+        );
+        assert_eq!(split_group(r#"('(' + ')')"#), Ok((r#"'(' + ')'"#, "")));
+        assert_eq!(split_group(r"('\'')"), Ok((r"'\''", "")));
+        assert_eq!(split_group("(`a)b`)"), Ok(("`a)b`", "")));
+        assert_eq!(
+            split_group("(`a ${ f(\"}\") } b)`) c"),
+            Ok(("`a ${ f(\"}\") } b)`", " c"))
+        );
+        assert_eq!(
+            split_group("(`${ `${ x }` }`)"),
+            Ok(("`${ `${ x }` }`", ""))
+        );
+    }
+
+    #[test]
+    fn comments() {
+        assert_eq!(split_group("(a /* ) */)"), Ok(("a /* ) */", "")));
+        assert_eq!(
+            split_group("(a /* /* ) */ ) */) b"),
+            Ok(("a /* /* ) */ ) */", " b"))
+        );
+        assert_eq!(split_group("(a // )\n)"), Ok(("a // )\n", "")));
+        assert_eq!(split_group("(a / b)"), Ok(("a / b", "")));
+    }
+
+    #[test]
+    fn errors() {
+        assert_eq!(split_group("(a"), Err(unterminated(0, ")")));
+        assert_eq!(split_group("(f(a)"), Err(unterminated(0, ")")));
+        assert_eq!(split_group("(f(a"), Err(unterminated(2, ")")));
+        assert_eq!(split_group("(a + [b)"), Err(unterminated(5, "]")));
+        assert_eq!(split_group("(#{ a: 1 )"), Err(unterminated(2, "}")));
+        assert_eq!(split_group(r#"(a + "b)"#), Err(unterminated(5, "\"")));
+        assert_eq!(split_group(r#"("b\")"#), Err(unterminated(1, "\"")));
+        assert_eq!(split_group("('a)"), Err(unterminated(1, "'")));
+        assert_eq!(split_group("(`a)"), Err(unterminated(1, "`")));
+        assert_eq!(split_group("(`${ a)`)"), Err(unterminated(2, "}")));
+        assert_eq!(split_group("(a /* b)"), Err(unterminated(3, "*/")));
+        assert_eq!(split_group("(a /* /* */ b)"), Err(unterminated(3, "*/")));
+        assert_eq!(split_group("(a // b)"), Err(unterminated(0, ")")));
+    }
+
+    #[test]
+    fn depths() {
+        let mut visited = vec![];
+        let result = scan("a[b] \"|\" (c) ] |", |_, ch, depth| {
+            visited.push((ch, depth));
+            ch == '|'
+        });
+
+        assert_eq!(result, Ok(Some(15)));
+        assert_eq!(
+            visited,
+            [
+                ('a', 0),
+                ('[', 0),
+                ('b', 1),
+                (']', 0),
+                (' ', 0),
+                (' ', 0),
+                ('(', 0),
+                ('c', 1),
+                (')', 0),
+                (' ', 0),
+                (']', 0),
+                (' ', 0),
+                ('|', 0),
+            ]
+        );
+    }
+}
@@ -0,0 +1,236 @@
+//! The `rand` module templates can `import`.
+//!
+//! It has the same functions as the `rhai-rand` package, but draws every number from
+//! the generator of the [`Environment`](super::Environment) being evaluated,
+//! so seeding the environment makes its output reproducible.
+
+use std::cell::RefCell;
+
+use rand::{rngs::StdRng, SeedableRng};
+use rhai::plugin::*;
+
+use super::environment::check_string_size;
+
+thread_local! {
+    // the generator of the environment currently evaluating on this thread,
+    // or `None` if it isn't seeded
+    static GENERATOR: RefCell<Option<StdRng>> = const { RefCell::new(None) };
+}
+
+/// Create a generator from a seed.
+pub fn generator(seed: u64) -> StdRng {
+    StdRng::seed_from_u64(seed)
+}
+
+/// Derive a seed from another seed and a key,
+/// such as the seed of a review session and the id of a card.
+///
+/// The same seed and key always give the same result, on every platform.
+///
+/// # Examples
+/// ```
+/// use flashmark::template::random::derive_seed;
+///
+/// assert_eq!(derive_seed(7, "card-1"), derive_seed(7, "card-1"));
+/// assert_ne!(derive_seed(7, "card-1"), derive_seed(7, "card-2"));
+/// assert_ne!(derive_seed(7, "card-1"), derive_seed(8, "card-1"));
+/// ```
+pub fn derive_seed(seed: u64, key: &str) -> u64 {
+    // FNV-1a, so the hash doesn't depend on the standard library's hasher
+    let hash = key.bytes().fold(0xcbf2_9ce4_8422_2325_u64, |hash, byte| {
+        (hash ^ u64::from(byte)).wrapping_mul(0x0100_0000_01b3)
+    });
+
+    split_mix(seed ^ split_mix(hash))
+}
+
+fn split_mix(value: u64) -> u64 {
+    let mut z = value.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+
+    z ^ (z >> 31)
+}
+
+/// Run `f` with `generator` used by every function of the module on this thread.
+///
+/// Scripts can reseed the generator while `f` runs,
+/// so it's written back afterwards.
+pub(crate) fn with_generator<T>(generator: &mut Option<StdRng>, f: impl FnOnce() -> T) -> T {
+    let previous = GENERATOR.replace(generator.take());
+    let result = f();
+    *generator = GENERATOR.replace(previous);
+
+    result
+}
+
+fn with_rng<T>(f: impl FnOnce(&mut dyn rand::RngCore) -> T) -> T {
+    GENERATOR.with_borrow_mut(|generator| match generator {
+        Some(rng) => f(rng),
+        None => f(&mut rand::thread_rng()),
+    })
+}
+
+/// Build the module.
+pub fn module() -> rhai::Module {
+    let mut module = rhai::Module::new();
+
+    combine_with_exported_module!(&mut module, "rand", rand_functions);
+    combine_with_exported_module!(&mut module, "array", array_functions);
+
+    module
+}
+
+fn empty_range(range: impl std::fmt::Debug) -> Box<EvalAltResult> {
+    EvalAltResult::ErrorArithmetic(format!("Range is empty: {range:?}"), Position::NONE).into()
+}
+
+#[export_module]
+mod rand_functions {
+    use rand::{
+        distributions::{Alphanumeric, DistString},
+        Rng,
+    };
+    use rhai::{FLOAT, INT};
+    use std::ops::{Range, RangeInclusive};
+
+    /// Seed the generator, making every number drawn afterwards reproducible.
+    pub fn seed(seed: INT) {
+        GENERATOR.set(Some(super::generator(seed as u64)));
+    }
+
+    /// Generate a random boolean value.
+    pub fn rand_bool() -> bool {
+        with_rng(|rng| rng.gen())
+    }
+
+    /// Generate a random boolean value, which is `true` with a probability.
+    #[rhai_fn(name = "rand_bool", return_raw)]
+    pub fn rand_bool_with_probability(probability: FLOAT) -> Result<bool, Box<EvalAltResult>> {
+        if !(0.0..=1.0).contains(&probability) {
+            return Err(EvalAltResult::ErrorArithmetic(
+                format!("Invalid probability (must be between 0.0 and 1.0): {probability}"),
+                Position::NONE,
+            )
+            .into());
+        }
+
+        Ok(with_rng(|rng| rng.gen_bool(probability)))
+    }
+
+    /// Generate a random string of letters and digits.
+    #[rhai_fn(name = "rand_alpha_numeric", return_raw)]
+    pub fn rand_alpha_numeric_of_length(
+        ctx: NativeCallContext,
+        length: INT,
+    ) -> Result<String, Box<EvalAltResult>> {
+        if length <= 0 {
+            return Err(EvalAltResult::ErrorArithmetic(
+                format!("String length must be positive: {length}"),
+                Position::NONE,
+            )
+            .into());
+        }
+
+        let length = usize::try_from(length).unwrap_or(usize::MAX);
+        check_string_size(&ctx, length)?;
+
+        Ok(with_rng(|rng| Alphanumeric.sample_string(rng, length)))
+    }
+
+    /// Generate a random integer.
+    pub fn rand() -> INT {
+        with_rng(|rng| rng.gen())
+    }
+
+    /// Generate a random integer in an exclusive range.
+    #[rhai_fn(name = "rand", return_raw)]
+    pub fn rand_exclusive_range(range: Range<INT>) -> Result<INT, Box<EvalAltResult>> {
+        if range.is_empty() {
+            return Err(empty_range(range));
+        }
+
+        Ok(with_rng(|rng| rng.gen_range(range)))
+    }
+
+    /// Generate a random integer in an inclusive range.
+    #[rhai_fn(name = "rand", return_raw)]
+    pub fn rand_inclusive_range(range: RangeInclusive<INT>) -> Result<INT, Box<EvalAltResult>> {
+        if range.is_empty() {
+            return Err(empty_range(range));
+        }
+
+        Ok(with_rng(|rng| rng.gen_range(range)))
+    }
+
+    /// Generate a random integer between `start` and `end`, both included.
+    #[rhai_fn(name = "rand", return_raw)]
+    pub fn rand_from_to_inclusive(start: INT, end: INT) -> Result<INT, Box<EvalAltResult>> {
+        if start > end {
+            return Err(empty_range(start..=end));
+        }
+
+        Ok(with_rng(|rng| rng.gen_range(start..=end)))
+    }
+
+    /// Generate a random float between 0.0 and 1.0.
+    pub fn rand_float() -> FLOAT {
+        with_rng(|rng| rng.gen())
+    }
+
+    /// Generate a random float between `start` and `end`, both included.
+    #[rhai_fn(name = "rand_float", return_raw)]
+    pub fn rand_float_range(start: FLOAT, end: FLOAT) -> Result<FLOAT, Box<EvalAltResult>> {
+        // the generator can't draw from a range that isn't finite, or is too wide to measure
+        if !(end - start).is_finite() {
+            return Err(EvalAltResult::ErrorArithmetic(
+                format!("Range must be finite: {start}..={end}"),
+                Position::NONE,
+            )
+            .into());
+        }
+
+        if start > end {
+            return Err(empty_range(start..=end));
+        }
+
+        Ok(with_rng(|rng| rng.gen_range(start..=end)))
+    }
+}
+
+#[export_module]
+mod array_functions {
+    use rand::seq::SliceRandom;
+    use rhai::{Array, INT};
+
+    /// Return a random element of the array, or `()` if it's empty.
+    #[rhai_fn(global)]
+    pub fn sample(array: &mut Array) -> Dynamic {
+        with_rng(|mut rng| array.choose(&mut rng).cloned()).unwrap_or(Dynamic::UNIT)
+    }
+
+    /// Return an amount of random elements of the array, in a random order.
+    #[rhai_fn(global, name = "sample")]
+    pub fn sample_with_amount(array: &mut Array, amount: INT) -> Array {
+        if amount <= 0 {
+            return Array::new();
+        }
+
+        with_rng(|mut rng| {
+            let mut sample: Array = array
+                .choose_multiple(&mut rng, amount as usize)
+                .cloned()
+                .collect();
+
+            // the order of chosen elements isn't fully random
+            sample.shuffle(&mut rng);
+            sample
+        })
+    }
+
+    /// Shuffle the elements of the array in place.
+    #[rhai_fn(global)]
+    pub fn shuffle(array: &mut Array) {
+        with_rng(|mut rng| array.shuffle(&mut rng));
+    }
+}
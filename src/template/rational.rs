@@ -0,0 +1,222 @@
+//! Exact fractions with an optional unit, like `3/4 cup`,
+//! which the default [`Formatter`](super::format::Formatter) writes as they are.
+//!
+//! Templates create them with `rational(3, 4)` or `rational(3, 4, "cup")`
+//! to write amounts which floats would round, like `1/3 cup`.
+
+use std::fmt;
+
+use rhai::{plugin::*, ImmutableString, INT};
+
+/// A fraction, always kept in lowest terms with a positive denominator.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Rational {
+    numer: INT,
+    denom: INT,
+    unit: Option<ImmutableString>,
+}
+
+impl Rational {
+    /// Create a fraction without a unit.
+    ///
+    /// Fails if the denominator is zero,
+    /// or the fraction doesn't fit in lowest terms, like `i64::MIN / -1`.
+    pub fn new(numer: INT, denom: INT) -> Result<Self, Box<EvalAltResult>> {
+        if denom == 0 {
+            return Err(arithmetic_error("Division by zero"));
+        }
+
+        // divided without their signs, since `i64::MIN` has no positive counterpart
+        let divisor = gcd(numer, denom).max(1);
+        let magnitude = numer.unsigned_abs() / divisor;
+
+        let numer = match numer.signum() == denom.signum() {
+            true => INT::try_from(magnitude).ok(),
+            false => INT::checked_sub_unsigned(0, magnitude),
+        };
+        let denom = INT::try_from(denom.unsigned_abs() / divisor).ok();
+
+        match numer.zip(denom) {
+            Some((numer, denom)) => Ok(Self {
+                numer,
+                denom,
+                unit: None,
+            }),
+            None => Err(arithmetic_error("Rational number overflowed")),
+        }
+    }
+
+    pub fn with_unit(mut self, unit: impl Into<ImmutableString>) -> Self {
+        let unit = unit.into();
+        self.unit = (!unit.is_empty()).then_some(unit);
+        self
+    }
+
+    pub fn numer(&self) -> INT {
+        self.numer
+    }
+
+    pub fn denom(&self) -> INT {
+        self.denom
+    }
+
+    pub fn unit(&self) -> Option<&str> {
+        self.unit.as_deref()
+    }
+
+    pub fn is_integer(&self) -> bool {
+        self.denom == 1
+    }
+
+    pub fn to_float(&self) -> rhai::FLOAT {
+        self.numer as rhai::FLOAT / self.denom as rhai::FLOAT
+    }
+
+    /// Split the fraction into its whole part and the rest, like `7/2` into `3` and `1/2`.
+    pub fn to_mixed(&self) -> (INT, Self) {
+        let whole = self.numer / self.denom;
+        let rest = Self {
+            numer: self.numer % self.denom,
+            ..self.clone()
+        };
+
+        (whole, rest)
+    }
+}
+
+impl From<INT> for Rational {
+    fn from(value: INT) -> Self {
+        Self {
+            numer: value,
+            denom: 1,
+            unit: None,
+        }
+    }
+}
+
+impl fmt::Display for Rational {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.denom {
+            1 => write!(f, "{}", self.numer)?,
+            _ => write!(f, "{}/{}", self.numer, self.denom)?,
+        }
+
+        match &self.unit {
+            Some(unit) => write!(f, " {unit}"),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Returns the greatest common divisor, which is unsigned
+/// because it's `2^63` for `i64::MIN` and itself.
+fn gcd(a: INT, b: INT) -> u64 {
+    let (mut a, mut b) = (a.unsigned_abs(), b.unsigned_abs());
+
+    while b != 0 {
+        (a, b) = (b, a % b);
+    }
+
+    a
+}
+
+fn arithmetic_error(message: &str) -> Box<EvalAltResult> {
+    EvalAltResult::ErrorArithmetic(message.into(), Position::NONE).into()
+}
+
+/// Build the module, which [`new_engine`](super::new_engine) registers globally.
+pub fn module() -> rhai::Module {
+    exported_module!(rational_functions)
+}
+
+#[export_module]
+mod rational_functions {
+    use rhai::{FLOAT, INT};
+
+    pub type Rational = super::Rational;
+
+    type RationalResult = Result<Rational, Box<EvalAltResult>>;
+
+    /// Create a fraction, like `rational(3, 4)`.
+    #[rhai_fn(return_raw)]
+    pub fn rational(numer: INT, denom: INT) -> RationalResult {
+        Rational::new(numer, denom)
+    }
+
+    /// Create a fraction with a unit, like `rational(3, 4, "cup")`.
+    #[rhai_fn(name = "rational", return_raw)]
+    pub fn rational_with_unit(numer: INT, denom: INT, unit: ImmutableString) -> RationalResult {
+        Ok(Rational::new(numer, denom)?.with_unit(unit))
+    }
+
+    /// Give a fraction a unit, or remove it with an empty string.
+    #[rhai_fn(name = "with_unit")]
+    pub fn with_unit(value: Rational, unit: ImmutableString) -> Rational {
+        value.with_unit(unit)
+    }
+
+    #[rhai_fn(get = "numer", pure)]
+    pub fn numer(value: &mut Rational) -> INT {
+        value.numer()
+    }
+
+    #[rhai_fn(get = "denom", pure)]
+    pub fn denom(value: &mut Rational) -> INT {
+        value.denom()
+    }
+
+    /// The unit of a fraction, or an empty string if it doesn't have one.
+    #[rhai_fn(get = "unit", pure)]
+    pub fn unit(value: &mut Rational) -> ImmutableString {
+        value.unit.clone().unwrap_or_default()
+    }
+
+    #[rhai_fn(name = "to_float", pure)]
+    pub fn to_float(value: &mut Rational) -> FLOAT {
+        value.to_float()
+    }
+
+    #[rhai_fn(name = "to_string", name = "to_debug", pure)]
+    pub fn to_string(value: &mut Rational) -> String {
+        value.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lowest_terms() {
+        let half = Rational::new(-2, -4).unwrap();
+        assert_eq!((half.numer(), half.denom()), (1, 2));
+
+        let negative = Rational::new(3, -6).unwrap();
+        assert_eq!((negative.numer(), negative.denom()), (-1, 2));
+
+        assert_eq!(Rational::new(0, 5).unwrap().to_string(), "0");
+        assert!(Rational::new(1, 0).is_err());
+    }
+
+    #[test]
+    fn extremes() {
+        let min = Rational::new(INT::MIN, 2).unwrap();
+        assert_eq!((min.numer(), min.denom()), (INT::MIN / 2, 1));
+
+        let max = Rational::new(INT::MIN + 1, -1).unwrap();
+        assert_eq!((max.numer(), max.denom()), (INT::MAX, 1));
+
+        let one = Rational::new(INT::MIN, INT::MIN).unwrap();
+        assert_eq!((one.numer(), one.denom()), (1, 1));
+
+        assert!(Rational::new(INT::MIN, -1).is_err());
+        assert!(Rational::new(1, INT::MIN).is_err());
+    }
+
+    #[test]
+    fn mixed() {
+        let cups = Rational::new(-7, 2).unwrap().with_unit("cup");
+        let (whole, rest) = cups.to_mixed();
+        assert_eq!((whole, rest.to_string()), (-3, "-1/2 cup".to_string()));
+    }
+}
@@ -1,10 +1,213 @@
 use super::{
-    parse::{Block, ForBlock, IfChainBlock, Line, Node},
-    Environment,
+    environment::RhaiIterator,
+    escape::{self, EscapeContext},
+    parse::{
+        Assignment, Block, Break, Call, Control, ControlFlow, ForBlock, IfChainBlock, Include,
+        Interpolation, Line, MatchBlock, Node, Segment, Trim, TrimSide, WhileBlock,
+    },
+    Diagnostic, DiagnosticKind, Environment, Error, Location, SourceMap,
 };
+use crate::slides::{BlockTracker, Boundary, LiteralBlock, Separators};
+
+/// The result of rendering a template, along with every diagnostic reported.
+///
+/// Parts of the template that failed to render are left out of the text.
+#[derive(Debug, Default)]
+pub struct Output {
+    pub text: String,
+    pub diagnostics: Vec<Diagnostic>,
+    pub source_map: SourceMap,
+    /// Added before every line that isn't blank, while rendering macros.
+    indent: String,
+    /// Set by `@break` and `@continue`, until the loop they're in handles it.
+    control: Option<Control>,
+    /// The amount of iterations of every loop so far.
+    iterations: usize,
+    /// Set once a limit is reached, to stop rendering anything else.
+    halted: bool,
+    /// Set by trim markers, until a line with something other than whitespace is rendered.
+    trim_next: bool,
+    /// The literal blocks of the current slide, where values are escaped differently.
+    blocks: BlockTracker,
+    /// Whether the current line is inside of the metadata block of a slide.
+    in_metadata: bool,
+    /// Whether a line was written since the start of the current slide,
+    /// after which a metadata block can't be opened.
+    in_slide: bool,
+    /// The amount of variables in scope before the template started rendering,
+    /// which are the only ones macros can see besides their parameters.
+    globals: usize,
+}
 
 pub trait Render {
-    fn render(&self, env: &mut Environment, unindent_amount: usize, output: &mut String);
+    fn render(&self, env: &mut Environment, unindent_amount: usize, output: &mut Output);
+}
+
+impl Output {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn report(&mut self, diagnostic: Diagnostic) {
+        self.diagnostics.push(diagnostic);
+    }
+
+    /// Stop rendering, reporting the limit that was reached.
+    fn halt(&mut self, location: Location, kind: DiagnosticKind) {
+        self.report(Diagnostic::new(location, kind));
+        self.halted = true;
+    }
+
+    /// Count an iteration of a loop, returning whether it can run.
+    fn count_iteration(&mut self, env: &Environment, location: Location) -> bool {
+        self.iterations += 1;
+
+        match env.limits().max_iterations {
+            Some(max) if self.iterations > max => {
+                self.halt(location, DiagnosticKind::IterationLimit(max));
+                false
+            }
+            _ => !self.halted,
+        }
+    }
+
+    /// Whether rendering should stop,
+    /// because of `@break`, `@continue` or a limit.
+    fn should_stop(&mut self, env: &Environment, location: Location) -> bool {
+        if let Some(max) = env.limits().max_output_size {
+            if !self.halted && self.text.len() > max {
+                self.halt(location, DiagnosticKind::OutputLimit(max));
+            }
+        }
+
+        self.halted || self.control.is_some()
+    }
+
+    /// Whether the next line rendered starts a new line of the output.
+    fn is_line_start(&self) -> bool {
+        self.text.is_empty() || self.text.ends_with('\n')
+    }
+
+    /// Returns the last line of the text, which hasn't ended yet.
+    fn current_line(&self) -> &str {
+        let start = self.text.rfind('\n').map_or(0, |i| i + 1);
+        &self.text[start..]
+    }
+
+    /// End the current line, keeping track of the blocks it opens or closes.
+    fn end_line(&mut self, separators: &Separators) {
+        let start = self.text.len() - self.current_line().len();
+        let line = &self.text[start..];
+
+        if !self.blocks.is_literal(line) {
+            // only the first line of a slide can open its metadata block
+            if line == "+++" && (self.in_metadata || !self.in_slide) {
+                self.in_metadata = !self.in_metadata;
+            }
+
+            if is_separator(separators, line) {
+                self.start_slide();
+                self.text.push('\n');
+                return;
+            }
+        }
+
+        self.in_slide = true;
+        self.text.push('\n');
+    }
+
+    /// Start a new slide, which starts outside of any block.
+    fn start_slide(&mut self) {
+        self.blocks = BlockTracker::default();
+        self.in_metadata = false;
+        self.in_slide = false;
+    }
+
+    /// Returns how a value written at the end of the text is escaped.
+    fn escape_context(&self) -> EscapeContext {
+        let line = self.current_line();
+        let at_line_start = line.trim().is_empty();
+
+        if self.in_metadata {
+            return EscapeContext::Metadata { at_line_start };
+        }
+
+        match self.blocks.open_block() {
+            Some(LiteralBlock::Fence { .. }) => EscapeContext::CodeBlock { at_line_start },
+            Some(_) => EscapeContext::Html,
+            // inside of a code span or inline math
+            None => match open_code_span(line) {
+                Some(backticks) => EscapeContext::CodeSpan { backticks },
+                None => EscapeContext::Markdown { at_line_start },
+            },
+        }
+    }
+
+    /// Remove the whitespace at the end of the text,
+    /// so whatever is rendered next continues the last line.
+    fn trim_end(&mut self) {
+        let was_line_start = self.is_line_start();
+
+        let len = self.text.trim_end().len();
+        let removed_lines = self.text[len..].matches('\n').count();
+        self.text.truncate(len);
+
+        // the last line left keeps its entry, even without its line break
+        let lines = match self.text.is_empty() {
+            true => 0,
+            false => self.source_map.len() + usize::from(was_line_start) - removed_lines,
+        };
+        self.source_map.truncate(lines);
+    }
+
+    /// Returns the rendered text if there were no diagnostics.
+    pub fn into_result(self) -> Result<String, Error> {
+        if self.diagnostics.is_empty() {
+            Ok(self.text)
+        } else {
+            Err(Error {
+                diagnostics: self.diagnostics,
+            })
+        }
+    }
+}
+
+/// Returns whether a line separates slides or cards.
+fn is_separator(separators: &Separators, line: &str) -> bool {
+    [Boundary::Slide, Boundary::Card]
+        .into_iter()
+        .any(|boundary| separators.get(boundary) == Some(line))
+}
+
+/// Returns the length of the run of backticks opening a code span
+/// that's still open at the end of a line.
+///
+/// Backticks escaped with a backslash don't open a span,
+/// but the ones inside of a span are taken literally.
+fn open_code_span(line: &str) -> Option<usize> {
+    let mut open = None;
+    let mut chars = line.chars().peekable();
+
+    while let Some(ch) = chars.next() {
+        match ch {
+            '\\' if open.is_none() => _ = chars.next(),
+            '`' => {
+                let mut run = 1;
+                while chars.next_if_eq(&'`').is_some() {
+                    run += 1;
+                }
+
+                open = match open {
+                    None => Some(run),
+                    Some(len) if len == run => None,
+                    open => open,
+                };
+            }
+            _ => (),
+        }
+    }
+
+    open
 }
 
 fn unindent(line: &str, amount: usize) -> &str {
@@ -21,126 +224,491 @@ fn unindent(line: &str, amount: usize) -> &str {
     }
 }
 
+impl<'a> Block<'a> {
+    /// Render a whole template,
+    /// whose variables in scope so far are global.
+    pub(crate) fn render_root(&self, env: &mut Environment, output: &mut Output) {
+        output.globals = env.scope_mut().len();
+        self.render(env, 0, output);
+    }
+}
+
 impl<'a> Render for Block<'a> {
-    fn render(&self, env: &mut Environment, unindent_amount: usize, output: &mut String) {
+    fn render(&self, env: &mut Environment, unindent_amount: usize, output: &mut Output) {
         let inner_unindent = self.min_indentation().saturating_sub(self.indent);
 
         let unindent_amount = unindent_amount + inner_unindent;
 
+        // variables declared with `@let` only live until the end of the block
+        let scope_len = env.scope_mut().len();
+
         for node in self.nodes.iter() {
             node.render(env, unindent_amount, output);
+
+            if output.should_stop(env, Location::new(node.line(), 1)) {
+                break;
+            }
         }
+
+        env.scope_mut().rewind(scope_len);
     }
 }
 
 impl<'a> Render for IfChainBlock<'a> {
-    fn render(&self, env: &mut Environment, unindent_amount: usize, output: &mut String) {
+    fn render(&self, env: &mut Environment, unindent_amount: usize, output: &mut Output) {
         match self.get_branch(env) {
             Some(Ok(block)) => block.render(env, unindent_amount, output),
-            Some(Err(err)) => {
-                use std::fmt::Write;
-
-                // the amount of indentation errors should have
-                let err_indent = self
-                    .min_indentation()
-                    .unwrap_or(0)
-                    .saturating_sub(unindent_amount);
+            Some(Err(err)) => output.report(err),
+            None => (),
+        }
+    }
+}
 
-                writeln!(output, "{:err_indent$}{}", "", err)
-                    .expect("writing to string can't fail");
-            }
+impl<'a> Render for MatchBlock<'a> {
+    fn render(&self, env: &mut Environment, unindent_amount: usize, output: &mut Output) {
+        match self.get_branch(env) {
+            Some(Ok(block)) => block.render(env, unindent_amount, output),
+            Some(Err(err)) => output.report(err),
             None => (),
         }
     }
 }
 
 impl<'a> Render for ForBlock<'a> {
-    fn render(&self, env: &mut Environment, unindent_amount: usize, output: &mut String) {
-        use std::fmt::Write;
-
-        // the amount of indentation errors should have
-        let err_indent = self.block.indent.saturating_sub(unindent_amount);
+    fn render(&self, env: &mut Environment, unindent_amount: usize, output: &mut Output) {
+        let location = self.iterable.location;
 
-        let iterable = match env.eval_ast(&self.iterable) {
+        let iterable = match env.eval_ast(&self.iterable.ast) {
             Ok(iterable) => iterable,
             Err(err) => {
-                writeln!(output, "{:err_indent$}{}", "", err)
-                    .expect("writing to string can't fail");
+                output.report(Diagnostic::eval(location, err));
                 return;
             }
         };
 
-        let iterator = match env.get_iter(iterable) {
-            Ok(iterator) => iterator,
+        let mut items = match env.get_iter(iterable) {
+            Ok(items) => items,
             Err(value) => {
-                writeln!(output, "{:err_indent$}{} is not iterable", "", value)
-                    .expect("writing to string can't fail");
+                let kind = DiagnosticKind::NotIterable(value.type_name().to_string());
+                output.report(Diagnostic::new(location, kind));
                 return;
             }
         };
 
-        for item in iterator {
-            match item {
-                Ok(value) => {
-                    env.scope_mut().push(self.binding, value);
-                    self.block.render(env, unindent_amount, output);
-                    env.scope_mut().pop();
+        // the length is only known if the iterator knows it ahead of time,
+        // since items are drawn one at a time, looking one ahead to know which one is last
+        let length = match items.size_hint() {
+            (lower, Some(upper)) if lower == upper => Some(lower),
+            _ => None,
+        };
+
+        let mut next = next_item(&mut items, location, output);
+
+        if next.is_none() {
+            if let Some(else_block) = &self.else_block {
+                else_block.render(env, unindent_amount, output);
+            }
+
+            return;
+        }
+
+        for index in 0.. {
+            let Some(item) = next.take() else {
+                break;
+            };
+
+            if !output.count_iteration(env, location) {
+                break;
+            }
+
+            next = next_item(&mut items, location, output);
+
+            let bound = match self.binding.item.bind(item) {
+                Ok(bound) => bound,
+                Err(value) => {
+                    let kind = DiagnosticKind::Destructure(
+                        value.type_name().to_string(),
+                        self.binding.item.to_string(),
+                    );
+                    output.report(Diagnostic::new(location, kind));
+                    continue;
                 }
+            };
+
+            let scope_len = env.scope_mut().len();
+            let scope = env.scope_mut();
+
+            scope.push(Self::LOOP, loop_object(index, next.is_none(), length));
+
+            if let Some(name) = &self.binding.index {
+                scope.push(&**name, index as rhai::INT);
+            }
+
+            for (name, value) in bound {
+                scope.push_dynamic(name, value);
+            }
+
+            self.block.render(env, unindent_amount, output);
+            env.scope_mut().rewind(scope_len);
+
+            if output.control.take() == Some(Control::Break) {
+                break;
+            }
+        }
+    }
+}
+
+impl<'a> Render for WhileBlock<'a> {
+    fn render(&self, env: &mut Environment, unindent_amount: usize, output: &mut Output) {
+        let location = self.condition.location;
+
+        loop {
+            match env.eval_ast::<bool>(&self.condition.ast) {
+                Ok(true) => (),
+                Ok(false) => break,
                 Err(err) => {
-                    writeln!(output, "{:err_indent$}{}", "", err)
-                        .expect("writing to string can't fail");
+                    output.report(Diagnostic::eval(location, err));
+                    break;
                 }
             }
+
+            if !output.count_iteration(env, location) {
+                break;
+            }
+
+            self.block.render(env, unindent_amount, output);
+
+            if output.control.take() == Some(Control::Break) {
+                break;
+            }
+        }
+    }
+}
+
+/// Returns the next item of a loop, reporting every error before it.
+fn next_item(
+    items: &mut RhaiIterator,
+    location: Location,
+    output: &mut Output,
+) -> Option<rhai::Dynamic> {
+    for item in items {
+        match item {
+            Ok(value) => return Some(value),
+            Err(err) => output.report(Diagnostic::eval(location, err)),
+        }
+    }
+
+    None
+}
+
+/// Describes the current iteration of a loop.
+///
+/// The length is `()` if it isn't known ahead of time.
+fn loop_object(index: usize, last: bool, length: Option<usize>) -> rhai::Map {
+    let mut object = rhai::Map::new();
+
+    object.insert("index".into(), (index as rhai::INT).into());
+    object.insert("first".into(), (index == 0).into());
+    object.insert("last".into(), last.into());
+    object.insert(
+        "length".into(),
+        length.map_or(rhai::Dynamic::UNIT, |length| (length as rhai::INT).into()),
+    );
+
+    object
+}
+
+impl Render for ControlFlow {
+    fn render(&self, _env: &mut Environment, _unindent_amount: usize, output: &mut Output) {
+        output.control = Some(self.control);
+    }
+}
+
+impl<'a> Render for Call<'a> {
+    fn render(&self, env: &mut Environment, unindent_amount: usize, output: &mut Output) {
+        let definition = &self.definition;
+
+        let args = match env.eval_ast::<rhai::Array>(&self.args.ast) {
+            Ok(args) => args,
+            Err(err) => {
+                output.report(Diagnostic::eval(self.args.location, err));
+                return;
+            }
+        };
+
+        if args.len() != definition.params.len() {
+            let kind = DiagnosticKind::MacroArguments(
+                definition.name.clone(),
+                definition.params.len(),
+                args.len(),
+            );
+            output.report(Diagnostic::new(self.location, kind));
+            return;
+        }
+
+        // the body has its own scope, where the caller's variables are hidden
+        let locals = take_locals(env, output.globals);
+
+        for (param, arg) in definition.params.iter().zip(args) {
+            env.scope_mut().push_dynamic(param.as_str(), arg);
+        }
+
+        let indent_len = output.indent.len();
+        output
+            .indent
+            .push_str(unindent(&self.indentation, unindent_amount));
+
+        definition.block.render(env, 0, output);
+
+        output.indent.truncate(indent_len);
+        env.scope_mut().rewind(output.globals);
+        env.scope_mut().extend(locals);
+    }
+}
+
+/// Remove every variable after the first `globals` from the scope, returning them.
+fn take_locals(env: &mut Environment, globals: usize) -> Vec<(String, bool, rhai::Dynamic)> {
+    let mut entries = std::mem::take(env.scope_mut())
+        .into_iter()
+        .map(|(name, value, _)| (name, value.is_read_only(), value));
+
+    *env.scope_mut() = entries.by_ref().take(globals).collect();
+
+    entries.collect()
+}
+
+impl Interpolation {
+    /// Evaluate the value, then pipe it through every filter.
+    pub fn eval(&self, env: &mut Environment) -> Result<rhai::Dynamic, Diagnostic> {
+        let mut value = env
+            .eval_ast::<rhai::Dynamic>(&self.value.ast)
+            .map_err(|err| Diagnostic::eval(self.value.location, err))?;
+
+        for filter in &self.filters {
+            let scope_len = env.scope_mut().len();
+            env.scope_mut().push_dynamic(Self::VALUE, value);
+
+            let result = env.eval_ast::<rhai::Dynamic>(&filter.ast);
+            env.scope_mut().rewind(scope_len);
+
+            value = result.map_err(|err| Diagnostic::eval(filter.location, err))?;
+        }
+
+        Ok(value)
+    }
+}
+
+impl Render for Interpolation {
+    fn render(&self, env: &mut Environment, _unindent_amount: usize, output: &mut Output) {
+        let value = match self.eval(env) {
+            Ok(value) => env.formatter().format(&value),
+            Err(err) => {
+                output.report(err);
+                return;
+            }
+        };
+
+        if self.is_raw || !env.autoescape() {
+            output.text.push_str(&value);
+            return;
+        }
+
+        let escaped = escape::escape(&value, output.escape_context());
+        output.text.push_str(&escaped);
+    }
+}
+
+impl<'a> Render for Segment<'a> {
+    fn render(&self, env: &mut Environment, unindent_amount: usize, output: &mut Output) {
+        match self {
+            Segment::Interpolation(interpolation) => {
+                interpolation.render(env, unindent_amount, output)
+            }
+            Segment::If(if_block) => if_block.render(env, 0, output),
+            Segment::For(for_block) => for_block.render(env, 0, output),
+        }
+    }
+}
+
+impl<'a> Line<'a> {
+    fn render_segments(&self, env: &mut Environment, output: &mut Output) {
+        for (segment, text) in &self.segments {
+            if let Some(segment) = segment {
+                segment.render(env, 0, output);
+            }
+
+            output.text.push_str(text);
         }
     }
 }
 
 impl<'a> Render for Line<'a> {
-    fn render(&self, env: &mut Environment, unindent_amount: usize, output: &mut String) {
-        let unindented = unindent(self.front, unindent_amount);
-        output.push_str(unindented);
+    fn render(&self, env: &mut Environment, unindent_amount: usize, output: &mut Output) {
+        // the body of an inline block is already part of an output line
+        if self.is_inline {
+            output.text.push_str(&self.front);
+            self.render_segments(env, output);
+            return;
+        }
 
-        for (expr, text) in &self.expressions {
-            use std::fmt::Write;
+        if self.trim_before {
+            output.trim_end();
+        }
 
-            let expr = match expr {
-                Ok(expr) => expr,
-                Err(err) => {
-                    write!(output, "{}", err).expect("writing to string can't fail");
-                    continue;
-                }
-            };
+        let is_line_start = output.is_line_start();
+        let start = output.text.len();
 
-            match env.eval_ast::<rhai::Dynamic>(expr) {
-                Ok(value) => {
-                    write!(output, "{}", value).expect("writing to string can't fail");
-                }
-                Err(err) => {
-                    write!(output, "{}", err).expect("writing to string can't fail");
-                }
+        let unindented = unindent(&self.front, unindent_amount);
+        output.text.push_str(unindented);
+
+        self.render_segments(env, output);
+
+        if output.trim_next {
+            let rendered = &output.text[start..];
+            let whitespace = rendered.len() - rendered.trim_start().len();
+            output.text.drain(start..start + whitespace);
+
+            // blank lines are skipped entirely
+            if output.text.len() == start {
+                return;
+            }
+
+            output.trim_next = false;
+        }
+
+        let is_empty = output.text.len() == start;
+
+        // a line joined onto the previous one is already part of an output line
+        if is_line_start && !(is_empty && self.trim_after) {
+            if !is_empty {
+                output.text.insert_str(start, &output.indent);
             }
 
-            output.push_str(text);
+            output.source_map.push(self.line);
+        }
+
+        if self.trim_after {
+            output.trim_next = true;
+        } else {
+            output.end_line(env.separators());
         }
-        output.push('\n');
+    }
+}
+
+impl Render for Trim {
+    fn render(&self, _env: &mut Environment, _unindent_amount: usize, output: &mut Output) {
+        match self.side {
+            TrimSide::Before => output.trim_end(),
+            TrimSide::After => output.trim_next = true,
+        }
+    }
+}
+
+impl<'a> Render for Assignment<'a> {
+    fn render(&self, env: &mut Environment, _unindent_amount: usize, output: &mut Output) {
+        let value = match env.eval_ast::<rhai::Dynamic>(&self.value.ast) {
+            Ok(value) => value,
+            Err(err) => {
+                output.report(Diagnostic::eval(self.value.location, err));
+                return;
+            }
+        };
+
+        let scope = env.scope_mut();
+
+        if self.is_declaration {
+            scope.push_dynamic(&*self.name, value);
+            return;
+        }
+
+        let error = match scope.is_constant(&self.name) {
+            Some(false) => {
+                scope.set_value(&*self.name, value);
+                return;
+            }
+            Some(true) => rhai::EvalAltResult::ErrorAssignmentToConstant(
+                self.name.to_string(),
+                rhai::Position::NONE,
+            ),
+            None => rhai::EvalAltResult::ErrorVariableNotFound(
+                self.name.to_string(),
+                rhai::Position::NONE,
+            ),
+        };
+
+        output.report(Diagnostic::eval(self.location, error.into()));
+    }
+}
+
+impl<'a> Render for Include<'a> {
+    fn render(&self, env: &mut Environment, unindent_amount: usize, output: &mut Output) {
+        let first_diagnostic = output.diagnostics.len();
+        let first_line = output.source_map.len();
+
+        self.block.render(env, unindent_amount, output);
+
+        let diagnostics = output.diagnostics.split_off(first_diagnostic);
+        output.diagnostics.extend(
+            diagnostics
+                .into_iter()
+                .map(|diagnostic| self.wrap(diagnostic)),
+        );
+
+        // the included lines aren't in this template, so map them to the directive
+        output.source_map.remap(first_line, self.location.line);
+    }
+}
+
+impl Render for Break {
+    fn render(&self, env: &mut Environment, _unindent_amount: usize, output: &mut Output) {
+        // a variable of the same name was most likely meant to be written
+        let name = self.boundary.to_string();
+        if env.scope_mut().contains(&name) {
+            output.report(Diagnostic::new(
+                self.location,
+                DiagnosticKind::ReservedName(name),
+            ));
+            return;
+        }
+
+        let Some(separator) = env.separators().get(self.boundary) else {
+            let kind = DiagnosticKind::NoSeparator(self.boundary);
+            output.report(Diagnostic::new(self.location, kind));
+            return;
+        };
+
+        // a card boundary before any content would only produce an empty card
+        if self.boundary == Boundary::Card && output.text.trim().is_empty() {
+            return;
+        }
+
+        // the last line already has an entry in the source map
+        if !output.is_line_start() {
+            output.text.push('\n');
+        }
+
+        output.text.push_str(separator);
+        output.text.push('\n');
+        output.source_map.push(self.location.line);
+
+        output.start_slide();
     }
 }
 
 impl<'a> Render for Node<'a> {
-    fn render(&self, env: &mut Environment, unindent_amount: usize, output: &mut String) {
+    fn render(&self, env: &mut Environment, unindent_amount: usize, output: &mut Output) {
         match self {
             Node::Line(line) => line.render(env, unindent_amount, output),
             Node::If(if_block) => if_block.render(env, unindent_amount, output),
+            Node::Match(match_block) => match_block.render(env, unindent_amount, output),
             Node::For(for_block) => for_block.render(env, unindent_amount, output),
-            Node::Err { indent, error } => {
-                use std::fmt::Write;
-
-                // the amount of indentation errors should have
-                let err_indent = indent.saturating_sub(unindent_amount);
-
-                writeln!(output, "{:err_indent$}{}", "", error)
-                    .expect("writing to string can't fail");
-            }
+            Node::While(while_block) => while_block.render(env, unindent_amount, output),
+            Node::Assign(assignment) => assignment.render(env, unindent_amount, output),
+            Node::Include(include) => include.render(env, unindent_amount, output),
+            Node::Call(call) => call.render(env, unindent_amount, output),
+            Node::Control(control) => control.render(env, unindent_amount, output),
+            Node::Trim(trim) => trim.render(env, unindent_amount, output),
+            Node::Break(slide_break) => slide_break.render(env, unindent_amount, output),
         }
     }
 }
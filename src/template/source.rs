@@ -0,0 +1,211 @@
+use std::fmt;
+
+/// A position in the template source.
+///
+/// Both the line and the column start at 1.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Location {
+    pub line: usize,
+    pub column: usize,
+}
+
+/// A range of bytes in the template source.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+}
+
+/// A single line of the template source, without its line break.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SourceLine<'a> {
+    /// The line number, starting at 1.
+    pub number: usize,
+    /// The byte offset of the start of the line in the template source.
+    pub offset: usize,
+    pub text: &'a str,
+}
+
+/// Maps lines of rendered output back to the template lines that produced them.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SourceMap {
+    lines: Vec<usize>,
+}
+
+impl Location {
+    pub fn new(line: usize, column: usize) -> Self {
+        Self { line, column }
+    }
+
+    /// Returns the location of a rhai position,
+    /// relative to the start of the script at this location.
+    ///
+    /// # Examples
+    /// ```
+    /// use flashmark::template::Location;
+    ///
+    /// let start = Location::new(3, 10);
+    ///
+    /// assert_eq!(start.offset(rhai::Position::new(1, 5)), Location::new(3, 14));
+    /// assert_eq!(start.offset(rhai::Position::new(2, 5)), Location::new(4, 5));
+    /// assert_eq!(start.offset(rhai::Position::NONE), start);
+    /// ```
+    pub fn offset(self, position: rhai::Position) -> Self {
+        let (Some(line), Some(column)) = (position.line(), position.position()) else {
+            return self;
+        };
+
+        if line <= 1 {
+            Self::new(self.line, self.column + column.saturating_sub(1))
+        } else {
+            Self::new(self.line + line - 1, column)
+        }
+    }
+}
+
+impl fmt::Display for Location {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}, column {}", self.line, self.column)
+    }
+}
+
+impl Span {
+    pub fn new(start: usize, end: usize) -> Self {
+        Self { start, end }
+    }
+
+    pub fn len(&self) -> usize {
+        self.end - self.start
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.start == self.end
+    }
+
+    /// Returns the smallest span containing both spans.
+    pub fn join(self, other: Span) -> Span {
+        Span::new(self.start.min(other.start), self.end.max(other.end))
+    }
+}
+
+impl<'a> SourceLine<'a> {
+    /// Split a part of the template source into numbered lines.
+    ///
+    /// `first_line` is the line number of the first line of `input`,
+    /// and `offset` is where `input` starts in the template source.
+    ///
+    /// # Examples
+    /// ```
+    /// use flashmark::template::SourceLine;
+    ///
+    /// let lines: Vec<_> = SourceLine::lines("a\r\nbc\nd", 5, 100).collect();
+    ///
+    /// assert_eq!(lines[1].number, 6);
+    /// assert_eq!(lines[1].offset, 103);
+    /// assert_eq!(lines[1].text, "bc");
+    /// assert_eq!(lines[2].offset, 106);
+    /// ```
+    pub fn lines(
+        input: &'a str,
+        first_line: usize,
+        offset: usize,
+    ) -> impl Iterator<Item = SourceLine<'a>> {
+        let mut next_offset = offset;
+
+        (first_line..)
+            .zip(input.split_inclusive('\n'))
+            .map(move |(number, raw)| {
+                let offset = next_offset;
+                next_offset += raw.len();
+
+                let text = raw.strip_suffix('\n').unwrap_or(raw);
+                let text = text.strip_suffix('\r').unwrap_or(text);
+
+                SourceLine {
+                    number,
+                    offset,
+                    text,
+                }
+            })
+    }
+
+    pub fn span(&self) -> Span {
+        Span::new(self.offset, self.offset + self.text.len())
+    }
+
+    /// Returns the span of `part`, a subslice of this line.
+    pub fn span_of(&self, part: &str) -> Span {
+        let start = self.offset + self.offset_of(part);
+        Span::new(start, start + part.len())
+    }
+
+    /// Returns the location of `part`, a subslice of this line.
+    pub fn location_of(&self, part: &str) -> Location {
+        let offset = self.offset_of(part);
+        let column = self
+            .text
+            .get(..offset)
+            .map_or(0, |prefix| prefix.chars().count())
+            + 1;
+
+        Location::new(self.number, column)
+    }
+
+    fn offset_of(&self, part: &str) -> usize {
+        (part.as_ptr() as usize).saturating_sub(self.text.as_ptr() as usize)
+    }
+}
+
+impl SourceMap {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Record that the next output line was produced by the given template line.
+    pub fn push(&mut self, template_line: usize) {
+        self.lines.push(template_line);
+    }
+
+    /// Map every output line from `start` onwards to a single template line.
+    ///
+    /// `start` is an index, starting at 0.
+    pub fn remap(&mut self, start: usize, template_line: usize) {
+        for line in self.lines.iter_mut().skip(start) {
+            *line = template_line;
+        }
+    }
+
+    /// Keep only the first `len` output lines.
+    pub fn truncate(&mut self, len: usize) {
+        self.lines.truncate(len);
+    }
+
+    /// Returns the template line that produced an output line.
+    ///
+    /// Both line numbers start at 1.
+    pub fn template_line(&self, output_line: usize) -> Option<usize> {
+        output_line
+            .checked_sub(1)
+            .and_then(|index| self.lines.get(index))
+            .copied()
+    }
+
+    /// Returns every output line produced by a template line,
+    /// which can be more than one inside of loops.
+    pub fn output_lines(&self, template_line: usize) -> impl Iterator<Item = usize> + '_ {
+        self.lines
+            .iter()
+            .enumerate()
+            .filter(move |(_, line)| **line == template_line)
+            .map(|(index, _)| index + 1)
+    }
+
+    /// Returns the amount of output lines.
+    pub fn len(&self) -> usize {
+        self.lines.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.lines.is_empty()
+    }
+}
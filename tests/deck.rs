@@ -0,0 +1,326 @@
+use indoc::indoc;
+
+#[test]
+fn front_and_back() {
+    let deck = flashmark::render(indoc! {"
+        What is *2 + 2*?
+        ---
+        **4**
+    "})
+    .unwrap();
+
+    assert_eq!(deck.len(), 1);
+
+    let card = &deck.cards[0];
+    assert_eq!(card.front, "<p>What is <em>2 + 2</em>?</p>\n");
+    assert_eq!(card.back.as_deref(), Some("<p><strong>4</strong></p>\n"));
+    assert!(card.extra.is_empty());
+}
+
+#[test]
+fn extra_faces() {
+    let deck = flashmark::render(indoc! {"
+        front
+        ---
+        back
+        ---
+        hint
+    "})
+    .unwrap();
+
+    let card = &deck.cards[0];
+    assert_eq!(card.extra, ["<p>hint</p>\n"]);
+}
+
+#[test]
+fn empty_document() {
+    assert!(flashmark::render("").unwrap().is_empty());
+}
+
+#[test]
+fn slide_metadata() {
+    let deck = flashmark::render(indoc! {r#"
+        ---
+        let country = "France";
+        ---
+        +++
+        id: capital-@(country.to_lower())
+        tags: geography, europe
+        type: basic
+        +++
+        What is the capital of @country?
+        ---
+        +++
+        tags: capitals
+        +++
+        Paris
+    "#})
+    .unwrap();
+
+    let card = &deck.cards[0];
+    assert_eq!(card.front, "<p>What is the capital of France?</p>\n");
+    assert_eq!(card.back.as_deref(), Some("<p>Paris</p>\n"));
+    assert_eq!(card.metadata.id.as_deref(), Some("capital-france"));
+    assert_eq!(card.metadata.kind.as_deref(), Some("basic"));
+    assert_eq!(card.metadata.tags, ["geography", "europe", "capitals"]);
+}
+
+#[test]
+fn many_cards() {
+    use flashmark::slides::LineSplitter;
+
+    let deck = flashmark::render_with_splitter(
+        indoc! {"
+            ---
+            let words = [\"one\", \"two\"];
+            ---
+            @for word in words
+                ===
+                @word
+                ---
+                @(word.len())
+            @end
+        "},
+        LineSplitter::cards(),
+    )
+    .unwrap();
+
+    assert_eq!(deck.len(), 2);
+    assert_eq!(deck.cards[0].front, "<p>one</p>\n");
+    assert_eq!(deck.cards[1].front, "<p>two</p>\n");
+    assert_eq!(deck.cards[1].back.as_deref(), Some("<p>3</p>\n"));
+}
+
+#[test]
+fn card_per_item() {
+    use flashmark::slides::LineSplitter;
+
+    let deck = flashmark::render_with_splitter(
+        indoc! {r#"
+            ---
+            let capitals = [["France", "Paris"], ["Japan", "Tokyo"], ["Peru", "Lima"]];
+            ---
+            @for pair in capitals
+                @card
+                What is the capital of @(pair[0])?
+                @slide
+                @(pair[1])
+            @end
+        "#},
+        LineSplitter::cards(),
+    )
+    .unwrap();
+
+    assert_eq!(deck.len(), 3);
+    assert_eq!(deck.cards[2].front, "<p>What is the capital of Peru?</p>\n");
+    assert_eq!(deck.cards[2].back.as_deref(), Some("<p>Lima</p>\n"));
+}
+
+#[test]
+fn card_per_item_with_builder() {
+    use flashmark::slides::LineSplitter;
+
+    let renderer = flashmark::Renderer::builder()
+        .splitter(LineSplitter::cards())
+        .build();
+
+    let deck = renderer
+        .render(indoc! {r#"
+            ---
+            let capitals = [["France", "Paris"], ["Japan", "Tokyo"]];
+            ---
+            @for pair in capitals
+                @card
+                What is the capital of @(pair[0])?
+                @slide
+                @(pair[1])
+            @end
+        "#})
+        .unwrap();
+
+    assert_eq!(deck.len(), 2);
+    assert_eq!(
+        deck.cards[1].front,
+        "<p>What is the capital of Japan?</p>\n"
+    );
+    assert_eq!(deck.cards[1].back.as_deref(), Some("<p>Tokyo</p>\n"));
+}
+
+#[test]
+fn default_keeps_setext_headings() {
+    let deck = flashmark::render("Title\n===\nbody\n").unwrap();
+
+    assert_eq!(deck.len(), 1);
+    assert_eq!(deck.cards[0].front, "<h1>Title</h1>\n<p>body</p>\n");
+}
+
+#[test]
+fn renderer_reuse() {
+    let renderer = flashmark::Renderer::new();
+
+    let first = renderer
+        .render(indoc! {r#"
+            ---
+            let name = "first";
+            ---
+            @name
+        "#})
+        .unwrap();
+    assert_eq!(first.cards[0].front, "<p>first</p>\n");
+
+    // variables from previous documents don't leak into the next ones
+    let (_, diagnostics) = renderer.render_lenient("@name");
+    assert_eq!(diagnostics.len(), 1);
+}
+
+#[test]
+fn renderer_compiled() {
+    let renderer = flashmark::Renderer::new();
+    let template = renderer
+        .compile(indoc! {"
+            What is @a + @b?
+            ---
+            @(a + b)
+        "})
+        .unwrap();
+
+    for (a, b) in [(1_i64, 2_i64), (3, 4)] {
+        let mut scope = rhai::Scope::new();
+        scope.push("a", a).push("b", b);
+
+        let deck = renderer.render_compiled(&template, scope).unwrap();
+        assert_eq!(deck.cards[0].front, format!("<p>What is {a} + {b}?</p>\n"));
+        assert_eq!(deck.cards[0].back, Some(format!("<p>{}</p>\n", a + b)));
+    }
+}
+
+#[test]
+fn renderer_seed() {
+    let input = indoc! {r#"
+        ---
+        import "rand" as rand;
+        let x = rand::rand(1, 1000000);
+        ---
+        What is @x squared?
+        ---
+        @(x * x)
+    "#};
+
+    let render = |seed| {
+        let renderer = flashmark::Renderer::builder().seed(seed).build();
+        renderer.render(input).unwrap()
+    };
+
+    assert_eq!(render(3).cards, render(3).cards);
+    assert_ne!(render(3).cards, render(4).cards);
+}
+
+#[test]
+fn renderer_file_loader() {
+    use flashmark::template::FileLoader;
+
+    let root = std::env::temp_dir().join(format!("flashmark-loader-{}", std::process::id()));
+    std::fs::create_dir_all(root.join("common")).unwrap();
+    std::fs::write(
+        root.join("common/units.md"),
+        "@include \"../symbol.md\"\nis @name",
+    )
+    .unwrap();
+    std::fs::write(root.join("symbol.md"), "`@symbol`").unwrap();
+
+    let renderer = flashmark::Renderer::builder()
+        .loader(FileLoader::new(&root))
+        .build();
+
+    let deck = renderer.render(indoc! {r#"
+        ---
+        let symbol = "m";
+        let name = "metre";
+        ---
+        @include "common/units.md"
+    "#});
+
+    std::fs::remove_dir_all(&root).unwrap();
+
+    assert_eq!(
+        deck.unwrap().cards[0].front,
+        "<p><code>m</code>\nis metre</p>\n"
+    );
+}
+
+#[test]
+fn renderer_safe() {
+    let renderer = flashmark::Renderer::safe();
+
+    let deck = renderer.render("What is @(6 * 7)?\n---\n42").unwrap();
+    assert_eq!(deck.cards[0].front, "<p>What is 42?</p>\n");
+
+    let (_, diagnostics) = renderer.render_lenient("---\nloop {}\n---\nfront");
+    assert_eq!(diagnostics.len(), 1);
+}
+
+#[test]
+fn renderer_splitter() {
+    let renderer = flashmark::Renderer::builder()
+        .splitter(flashmark::LineSplitter::cards())
+        .build();
+
+    let deck = renderer.render("a\n@card\nb").unwrap();
+    assert_eq!(deck.len(), 2);
+}
+
+#[cfg(feature = "sync")]
+#[test]
+fn renderer_threads() {
+    let renderer = flashmark::Renderer::new();
+
+    std::thread::scope(|scope| {
+        let handles: Vec<_> = (0..4)
+            .map(|i| {
+                let renderer = &renderer;
+                scope.spawn(move || renderer.render(&format!("@({i} * 2)")).unwrap())
+            })
+            .collect();
+
+        for (i, handle) in handles.into_iter().enumerate() {
+            let deck = handle.join().unwrap();
+            assert_eq!(deck.cards[0].front, format!("<p>{}</p>\n", i * 2));
+        }
+    });
+}
+
+#[test]
+fn comments() {
+    let deck = flashmark::render(indoc! {"
+        What is *2 + 2*? @# too easy?
+        @*
+        ---
+        a back that isn't there
+        *@
+        ---
+        **4**
+    "})
+    .unwrap();
+
+    let card = &deck.cards[0];
+    assert_eq!(card.front, "<p>What is <em>2 + 2</em>?</p>\n");
+    assert_eq!(card.back.as_deref(), Some("<p><strong>4</strong></p>\n"));
+    assert!(card.extra.is_empty());
+}
+
+#[test]
+fn escaped_values() {
+    let mut scope = rhai::Scope::new();
+    scope.push("answer", "<script>alert(1)</script>\n---\n$`4`$");
+
+    let renderer = flashmark::Renderer::new();
+    let template = renderer.compile("What is 2 + 2?\n---\n@answer").unwrap();
+    let deck = renderer.render_compiled(&template, scope).unwrap();
+
+    let card = &deck.cards[0];
+    assert_eq!(
+        card.back.as_deref(),
+        Some("<p>&lt;script&gt;alert(1)&lt;/script&gt;\n---\n$`4`$</p>\n")
+    );
+    assert!(card.extra.is_empty());
+}
@@ -1,19 +1,32 @@
+use flashmark::template;
 use indoc::indoc;
 
 fn test_render(input: &str, expected: &str) {
-    let actual = flashmark::template::render(input);
+    let actual = flashmark::template::render(input).unwrap();
     assert_eq!(actual.trim_end(), expected.trim_end());
 }
 
 fn test_render_with_scope(scope: rhai::Scope<'static>, input: &str, expected: &str) {
-    use flashmark::template;
     let engine = template::new_engine();
     let env = template::Environment::with_scope(engine, scope);
-    let actual = template::render_with_environment(env, input);
+    let actual = template::render_with_environment(env, input).unwrap();
 
     assert_eq!(actual.trim_end(), expected.trim_end());
 }
 
+fn test_diagnostics(input: &str, expected_output: &str, expected_locations: &[(usize, usize)]) {
+    let output = flashmark::template::render_lenient(input);
+
+    let locations: Vec<_> = output
+        .diagnostics
+        .iter()
+        .map(|diagnostic| (diagnostic.location.line, diagnostic.location.column))
+        .collect();
+
+    assert_eq!(output.text.trim_end(), expected_output.trim_end());
+    assert_eq!(locations, expected_locations);
+}
+
 #[test]
 fn front_matter() {
     test_render(
@@ -293,3 +306,1718 @@ fn for_nested() {
         "(1, 1)\n(1, 2)\n(1, 3)\n(2, 1)\n(2, 2)\n(2, 3)\n(3, 1)\n(3, 2)\n(3, 3)",
     );
 }
+
+#[test]
+fn error_expression_parse() {
+    test_diagnostics("Hello, @(1 +)!", "Hello, !", &[(1, 13)]);
+}
+
+#[test]
+fn error_expression_eval() {
+    test_diagnostics(
+        indoc! {"
+            first
+            second @missing third
+        "},
+        "first\nsecond  third",
+        &[(2, 9)],
+    );
+}
+
+#[test]
+fn error_front_matter() {
+    test_diagnostics(
+        indoc! {r#"
+            ---
+            let x = 1;
+            let y = x.nope();
+            ---
+            @x
+        "#},
+        "1",
+        &[(3, 11)],
+    );
+}
+
+#[test]
+fn error_front_matter_parse() {
+    test_diagnostics(
+        indoc! {"
+            ---
+            let x = ;
+            ---
+            text
+        "},
+        "text",
+        &[(2, 9)],
+    );
+}
+
+#[test]
+fn error_line_after_front_matter() {
+    test_diagnostics(
+        indoc! {"
+            ---
+            let x = 1;
+            ---
+            @x
+            @(x.nope())
+        "},
+        "1",
+        &[(5, 5)],
+    );
+}
+
+#[test]
+fn error_if_condition() {
+    test_diagnostics(
+        indoc! {"
+            @if 1 +
+                a
+            @elif false
+                b
+            @end
+            after
+        "},
+        "after",
+        &[(1, 8)],
+    );
+    test_diagnostics(
+        indoc! {"
+            @if 1
+                a
+            @end
+        "},
+        "",
+        &[(1, 5)],
+    );
+}
+
+#[test]
+fn error_for_not_iterable() {
+    test_diagnostics(
+        indoc! {"
+            @for x in 5
+                @x
+            @end
+        "},
+        "",
+        &[(1, 11)],
+    );
+}
+
+#[test]
+fn error_every_diagnostic() {
+    test_diagnostics(
+        indoc! {"
+            @if false
+                @(1 +)
+            @end
+            @a @b
+        "},
+        "",
+        &[(2, 10), (4, 2), (4, 5)],
+    );
+}
+
+#[test]
+fn error_strict() {
+    let err = flashmark::template::render("@missing").unwrap_err();
+
+    assert_eq!(err.diagnostics.len(), 1);
+    assert!(matches!(
+        err.diagnostics[0].kind,
+        flashmark::template::DiagnosticKind::Eval(_)
+    ));
+}
+
+#[test]
+fn source_map() {
+    let output = flashmark::template::render_lenient(indoc! {"
+        ---
+        let xs = [1, 2];
+        ---
+        first
+        @for x in xs
+            @if x == 2
+                two
+            @end
+            item @x
+        @end
+        last
+    "});
+
+    let map = &output.source_map;
+
+    assert_eq!(output.text, "first\nitem 1\ntwo\nitem 2\nlast\n");
+    assert_eq!(map.len(), 5);
+    assert_eq!(map.template_line(1), Some(4));
+    assert_eq!(map.template_line(2), Some(9));
+    assert_eq!(map.template_line(3), Some(7));
+    assert_eq!(map.template_line(5), Some(11));
+    assert_eq!(map.template_line(6), None);
+    assert_eq!(map.output_lines(9).collect::<Vec<_>>(), [2, 4]);
+}
+
+#[test]
+fn error_location_in_nested_block() {
+    test_diagnostics(
+        indoc! {"
+            ---
+            let xs = [1];
+            ---
+            @for x in xs
+                @if x == 1
+                    @(x.nope())
+                @end
+            @end
+        "},
+        "",
+        &[(6, 13)],
+    );
+}
+
+#[test]
+fn slide_directive() {
+    test_render(
+        indoc! {"
+            @for x in [1, 2]
+                @x
+                @slide
+            @end
+            end
+        "},
+        "1\n---\n2\n---\nend",
+    );
+}
+
+#[test]
+fn card_directive_without_separator() {
+    test_diagnostics(
+        indoc! {"
+            front
+              @card
+            back
+        "},
+        "front\nback",
+        &[(2, 3)],
+    );
+}
+
+#[test]
+fn card_directive() {
+    use flashmark::{slides, template};
+
+    let mut env = template::Environment::with_engine(template::new_engine());
+    env.set_separators(slides::Separators::of(&slides::LineSplitter::cards()));
+
+    let output = template::render_with_environment(
+        env,
+        indoc! {"
+            @for x in [1, 2]
+                @card
+                    front @x
+                @slide
+                    back @x
+            @end
+        "},
+    )
+    .unwrap();
+
+    assert_eq!(output, "front 1\n---\nback 1\n===\nfront 2\n---\nback 2\n");
+}
+
+#[test]
+fn card_directive_named_variable() {
+    use flashmark::{slides, template};
+
+    let mut env = template::Environment::with_engine(template::new_engine());
+    env.set_separators(slides::Separators::of(&slides::LineSplitter::cards()));
+
+    let output = template::render_lenient_with_environment(
+        env,
+        indoc! {r#"
+            @for card in ["a", "b"]
+            @card
+            @(card)
+            @end
+        "#},
+    );
+
+    let locations: Vec<_> = output
+        .diagnostics
+        .iter()
+        .map(|diagnostic| (diagnostic.location.line, diagnostic.location.column))
+        .collect();
+
+    assert_eq!(output.text, "a\nb\n");
+    assert_eq!(locations, [(2, 1), (2, 1)]);
+    assert!(matches!(
+        output.diagnostics[0].kind,
+        template::DiagnosticKind::ReservedName(_)
+    ));
+}
+
+#[test]
+fn compiled_template() {
+    use flashmark::template::{self, CompiledTemplate};
+
+    let template = CompiledTemplate::compile(
+        template::new_engine(),
+        indoc! {"
+            ---
+            let double = n * 2;
+            ---
+            @n doubled is @double
+        "},
+    )
+    .unwrap();
+
+    for n in [1_i64, 5, 21] {
+        let mut scope = rhai::Scope::new();
+        scope.push("n", n);
+
+        let output = template.render(scope).unwrap();
+        assert_eq!(output, format!("{n} doubled is {}\n", n * 2));
+    }
+}
+
+#[test]
+fn compiled_template_errors() {
+    use flashmark::template::{self, CompiledTemplate};
+
+    let err = CompiledTemplate::compile(template::new_engine(), "ok\n@(1 +)\n")
+        .err()
+        .unwrap();
+
+    let locations: Vec<_> = err
+        .diagnostics
+        .iter()
+        .map(|diagnostic| (diagnostic.location.line, diagnostic.location.column))
+        .collect();
+
+    assert_eq!(locations.len(), 1);
+    assert_eq!(locations[0].0, 2);
+
+    let template = CompiledTemplate::compile(template::new_engine(), "@missing\n").unwrap();
+    let output = template.render_lenient(template.environment());
+
+    assert_eq!(output.diagnostics.len(), 1);
+}
+
+#[test]
+fn seeded_rand() {
+    use flashmark::template::{self, CompiledTemplate};
+
+    let template = CompiledTemplate::compile(
+        template::new_engine(),
+        indoc! {r#"
+            ---
+            import "rand" as rand;
+
+            let numbers = [rand::rand(1, 1000), rand::rand(1, 1000), rand::rand(1, 1000)];
+            let letters = ["a", "b", "c", "d", "e"];
+            letters.shuffle();
+            ---
+            @numbers @letters
+        "#},
+    )
+    .unwrap();
+
+    let render = |seed| {
+        let mut env = template.environment();
+        env.set_seed(seed);
+
+        template.render_lenient(env).into_result().unwrap()
+    };
+
+    assert_eq!(render(1), render(1));
+    assert_ne!(render(1), render(2));
+}
+
+#[test]
+fn seeded_rand_from_script() {
+    let input = indoc! {r#"
+        ---
+        import "rand" as rand;
+        rand::seed(42);
+        let x = rand::rand(1, 1000000);
+        let y = rand::rand_float();
+        ---
+        @x @y
+    "#};
+
+    assert_eq!(
+        flashmark::template::render(input).unwrap(),
+        flashmark::template::render(input).unwrap()
+    );
+}
+
+#[test]
+fn rand_inclusive_range() {
+    test_render(
+        indoc! {r#"
+            ---
+            import "rand" as rand;
+            let x = rand::rand(5, 5);
+            let y = rand::rand_float(0.5, 0.5);
+            ---
+            @x @y
+        "#},
+        "5 0.5",
+    );
+
+    let error = flashmark::template::render(indoc! {r#"
+        ---
+        import "rand" as rand;
+        let x = rand::rand(6, 5);
+        ---
+    "#});
+
+    assert!(error.is_err());
+}
+
+#[test]
+fn rand_invalid_arguments() {
+    for call in [
+        "rand::rand_float(0.0, 1.0 / 0.0)",
+        "rand::rand_float(0.0 / 0.0, 1.0)",
+        "rand::rand_float(-1e308, 1e308)",
+        "rand::rand_alpha_numeric(0)",
+    ] {
+        let input = format!("---\nimport \"rand\" as rand;\nlet x = {call};\n---\n");
+        assert!(flashmark::template::render(&input).is_err(), "{call}");
+    }
+
+    let output = render_safe(indoc! {r#"
+        ---
+        import "rand" as rand;
+        let x = rand::rand_alpha_numeric(4611686018427387904);
+        ---
+    "#});
+
+    assert!(is_resource_limit(&output));
+}
+
+#[test]
+fn let_directive() {
+    test_render(
+        indoc! {"
+            @let x = 6 * 7
+            The answer is @x
+            @for i in [1, 2]
+                @let square = i * i
+                @i squared is @square
+            @end
+        "},
+        "The answer is 42\n1 squared is 1\n2 squared is 4",
+    );
+}
+
+#[test]
+fn let_directive_scope() {
+    test_diagnostics(
+        indoc! {"
+            @if true
+                @let inner = 1
+                @inner
+            @end
+            @inner
+        "},
+        "1\n",
+        &[(5, 2)],
+    );
+}
+
+#[test]
+fn set_directive() {
+    test_render(
+        indoc! {"
+            @let total = 0
+            @for x in [1, 2, 3]
+                @set total = total + x
+            @end
+            Total: @total
+        "},
+        "Total: 6",
+    );
+}
+
+#[test]
+fn error_set_directive() {
+    test_diagnostics(
+        indoc! {r#"
+            ---
+            const name = "constant";
+            ---
+            @set missing = 1
+            @set name = "changed"
+            @let = 1
+            @let x = (1 +
+            @name
+        "#},
+        "constant",
+        &[(6, 1), (7, 14), (4, 6), (5, 6)],
+    );
+}
+
+fn render_with_loader(loader: template::MemoryLoader, input: &str) -> template::Output {
+    let mut env = template::Environment::with_engine(template::new_engine());
+    env.set_loader(loader);
+
+    template::render_lenient_with_environment(env, input)
+}
+
+#[test]
+fn include_directive() {
+    let loader = template::MemoryLoader::new()
+        .with("row", "| @unit | @factor |")
+        .with("header", "| unit | factor |\n|------|--------|");
+
+    let output = render_with_loader(
+        loader,
+        indoc! {r#"
+            @include "header"
+            @for pair in [["km", 1000], ["cm", 0.01]]
+                @let unit = pair[0]
+                @let factor = pair[1]
+                @include "row"
+            @end
+        "#},
+    );
+
+    assert!(output.diagnostics.is_empty());
+    assert_eq!(
+        output.text,
+        "| unit | factor |\n|------|--------|\n| km | 1000 |\n| cm | 0.01 |\n"
+    );
+    assert_eq!(output.source_map.template_line(2), Some(1));
+    assert_eq!(output.source_map.template_line(3), Some(5));
+}
+
+#[test]
+fn include_directive_indentation() {
+    let loader = template::MemoryLoader::new().with("list", "- a\n    - b");
+
+    let output = render_with_loader(
+        loader,
+        indoc! {r#"
+            - items
+                @include "list"
+            @if true
+                @include "list"
+            @end
+        "#},
+    );
+
+    assert_eq!(output.text, "- items\n    - a\n        - b\n- a\n    - b\n");
+}
+
+#[test]
+fn error_include_directive() {
+    let loader = template::MemoryLoader::new()
+        .with("self", "@include \"self\"")
+        .with("broken", "ok\n  @(1 +)\n@missing");
+
+    let output = render_with_loader(
+        loader,
+        indoc! {r#"
+            @include missing
+            @include "missing"
+            @include "self"
+              @include "broken"
+        "#},
+    );
+
+    let diagnostics: Vec<_> = output
+        .diagnostics
+        .iter()
+        .map(|diagnostic| {
+            let location = diagnostic.location;
+            let inner = match &diagnostic.kind {
+                template::DiagnosticKind::Included(_, inner) => {
+                    Some((inner.location.line, inner.location.column))
+                }
+                _ => None,
+            };
+
+            ((location.line, location.column), inner)
+        })
+        .collect();
+
+    assert_eq!(
+        diagnostics,
+        [
+            ((1, 10), None),
+            ((2, 10), None),
+            ((3, 10), Some((1, 10))),
+            ((4, 12), Some((2, 8))),
+            ((4, 12), Some((3, 2))),
+        ]
+    );
+}
+
+#[test]
+fn include_without_loader() {
+    test_diagnostics("@include \"file\"", "", &[(1, 10)]);
+}
+
+#[test]
+fn macro_directive() {
+    test_render(
+        indoc! {r#"
+            @macro definition(term, meaning)
+                **@term**
+                : @meaning
+            @end
+            @macro rule()
+                ***
+            @end
+            @definition("Ohm", "the unit of resistance")
+            @rule()
+            @for unit in ["Volt", "Ampere"]
+                - @unit
+                    @definition(unit, "a unit")
+            @end
+        "#},
+        indoc! {"
+            **Ohm**
+            : the unit of resistance
+            ***
+            - Volt
+                **Volt**
+                : a unit
+            - Ampere
+                **Ampere**
+                : a unit
+        "},
+    );
+}
+
+#[test]
+fn macro_directive_scope() {
+    test_diagnostics(
+        indoc! {r#"
+            @macro greet(name)
+                Hello, @name!
+            @end
+            @greet("World")
+            @name
+        "#},
+        "Hello, World!\n",
+        &[(5, 2)],
+    );
+}
+
+#[test]
+fn macro_directive_own_scope() {
+    test_diagnostics(
+        indoc! {r#"
+            ---
+            let greeting = "Hello";
+            ---
+            @macro greet(name)
+                @greeting, @name!@secret
+                @set greeting = "Bye"
+            @end
+            @let secret = "?"
+            @for name in ["World"]
+                @greet("you")
+                @name @secret
+            @end
+            @greeting
+        "#},
+        "Hello, you!\nWorld ?\nBye",
+        &[(5, 23)],
+    );
+}
+
+#[test]
+fn error_macro_directive() {
+    test_diagnostics(
+        indoc! {r#"
+            @macro broken(a, a)
+            @end
+            @macro if(a)
+            @end
+            @macro pair(a, b)
+                @a @b
+            @end
+            @pair(1)
+            @pair(1, (2 +))
+            @unknown(1)
+        "#},
+        "",
+        &[(1, 8), (3, 8), (9, 14), (8, 1), (10, 2)],
+    );
+}
+
+#[test]
+fn match_directive() {
+    test_render(
+        indoc! {r#"
+            @for unit in ["m", "s", "kg", "K"]
+                @match unit
+                    @case "m"
+                        length
+                    @case "s", "min"
+                        time
+                    @else
+                        other
+                @end
+            @end
+        "#},
+        "length\ntime\nother\nother",
+    );
+}
+
+#[test]
+fn match_directive_evaluates_subject_once() {
+    use std::sync::{
+        atomic::{AtomicI64, Ordering},
+        Arc,
+    };
+
+    let count = Arc::new(AtomicI64::new(0));
+
+    let mut engine = template::new_engine();
+    let counter = count.clone();
+    engine.register_fn("next", move || counter.fetch_add(1, Ordering::SeqCst) + 1);
+
+    let output = template::render_with_engine(
+        engine,
+        indoc! {"
+            @match next()
+                @case 3
+                    three
+                @case 2
+                    two
+                @case 1
+                    one
+            @end
+        "},
+    )
+    .unwrap();
+
+    assert_eq!(output, "one\n");
+    assert_eq!(count.load(Ordering::SeqCst), 1);
+}
+
+#[test]
+fn error_match_directive() {
+    test_diagnostics(
+        indoc! {r#"
+            @match 1
+                oops
+                @case 1, (2 +)
+                    one
+            @end
+            @match nope
+                @case 1
+                    one
+            @end
+            @match 1
+                @case 1
+                    one
+            @end
+        "#},
+        "one",
+        &[(2, 1), (3, 18), (6, 8)],
+    );
+}
+
+#[test]
+fn for_index_and_loop_object() {
+    test_render(
+        indoc! {r#"
+            @for (i, x) in ["a", "b", "c"]
+                @i @x @(forloop.index) @(forloop.first) @(forloop.last) @(forloop.length)
+            @end
+        "#},
+        "0 a 0 true false 3\n1 b 1 false false 3\n2 c 2 false true 3",
+    );
+}
+
+#[test]
+fn for_destructuring() {
+    test_render(
+        indoc! {r#"
+            @for [unit, factor] in [["km", 1000], ["cm", 0.01]]
+                @unit = @factor m
+            @end
+            @for {name, symbol} in [#{ name: "metre", symbol: "m" }]
+                @name (@symbol)
+            @end
+            @for (i, [key, value]) in #{ b: 2, a: 1 }
+                @i: @key => @value
+            @end
+        "#},
+        "km = 1000 m\ncm = 0.01 m\nmetre (m)\n0: a => 1\n1: b => 2",
+    );
+}
+
+#[test]
+fn for_else() {
+    test_render(
+        indoc! {"
+            @for x in []
+                @x
+            @else
+                empty
+            @end
+            @for x in [1]
+                @x
+            @else
+                empty
+            @end
+        "},
+        "empty\n1",
+    );
+}
+
+#[test]
+fn break_and_continue() {
+    test_render(
+        indoc! {"
+            @for x in 1..10
+                @if x % 2 == 0
+                    @continue
+                @end
+                @for y in 1..10
+                    @if y > x
+                        @break
+                    @end
+                    @x@y
+                @end
+                @if x >= 5
+                    @break
+                @end
+            @end
+        "},
+        "11\n31\n32\n33\n51\n52\n53\n54\n55",
+    );
+}
+
+#[test]
+fn error_for_bindings() {
+    test_diagnostics(
+        indoc! {"
+            @for [a, b] in [[1, 2], [3], 4]
+                @a @b
+            @end
+            @for (i) in [1]
+            @end
+            @break
+            @macro skip()
+                @continue
+            @end
+        "},
+        "1 2",
+        &[(4, 6), (6, 1), (8, 5), (1, 16), (1, 16)],
+    );
+}
+
+fn render_with_limits(limits: template::Limits, input: &str) -> template::Output {
+    let mut env = template::Environment::with_engine(template::new_engine());
+    env.set_limits(limits);
+
+    template::render_document(env, input)
+}
+
+#[test]
+fn while_directive() {
+    test_render(
+        indoc! {"
+            @let n = 27
+            @let steps = 0
+            @while n != 1
+                @if n % 2 == 0
+                    @set n = n / 2
+                @else
+                    @set n = 3 * n + 1
+                @end
+                @set steps = steps + 1
+                @if steps >= 10
+                    @break
+                @end
+            @end
+            @steps steps, reached @n
+        "},
+        "10 steps, reached 214",
+    );
+}
+
+#[test]
+fn error_iteration_limit() {
+    let limits = template::Limits {
+        max_iterations: Some(100),
+        ..template::Limits::UNLIMITED
+    };
+
+    let output = render_with_limits(
+        limits,
+        indoc! {"
+            @while true
+            @end
+            never rendered
+        "},
+    );
+
+    assert_eq!(output.text, "");
+    assert!(matches!(
+        output.diagnostics[..],
+        [template::Diagnostic {
+            kind: template::DiagnosticKind::IterationLimit(100),
+            ..
+        }]
+    ));
+
+    // the limit is shared by every loop, even huge ranges
+    let output = render_with_limits(
+        limits,
+        indoc! {"
+            @for x in 0..60
+            @end
+            @for x in 0..1000000000000
+            @end
+        "},
+    );
+
+    assert_eq!(output.diagnostics.len(), 1);
+    assert_eq!(output.diagnostics[0].location.line, 3);
+}
+
+#[test]
+fn for_huge_range_without_limits() {
+    // items are drawn one at a time, so the range is never collected
+    let output = render_with_limits(
+        template::Limits::UNLIMITED,
+        indoc! {"
+            @for i in 0..9223372036854775807
+                @(forloop.first) @(forloop.length)
+                @if i == 1
+                    @break
+                @end
+            @end
+            @for c in \"ab\"
+                @c @(forloop.last)
+            @end
+        "},
+    );
+
+    assert_eq!(
+        output.into_result().unwrap(),
+        "true 9223372036854775807\nfalse 9223372036854775807\na false\nb true\n"
+    );
+}
+
+#[test]
+fn error_output_limit() {
+    let limits = template::Limits {
+        max_output_size: Some(1000),
+        ..template::Limits::UNLIMITED
+    };
+
+    let output = render_with_limits(
+        limits,
+        indoc! {"
+            @for x in 0..1000
+                line @x
+            @end
+        "},
+    );
+
+    assert!(output.text.len() < 1100);
+    assert!(matches!(
+        output.diagnostics[..],
+        [template::Diagnostic {
+            kind: template::DiagnosticKind::OutputLimit(1000),
+            ..
+        }]
+    ));
+}
+
+fn render_safe(input: &str) -> template::Output {
+    template::render_lenient_with_engine(template::new_safe_engine(), input)
+}
+
+fn is_resource_limit(output: &template::Output) -> bool {
+    matches!(
+        output.diagnostics[..],
+        [template::Diagnostic {
+            kind: template::DiagnosticKind::ResourceLimit(_),
+            ..
+        }]
+    )
+}
+
+#[test]
+fn safe_engine_infinite_loops() {
+    for script in [
+        "loop {}",
+        "while true {}",
+        "let x = 0; do { x += 1; } while x >= 0;",
+        "fn f(x) { f(x + 1) } f(0);",
+    ] {
+        let output = render_safe(&format!("---\n{script}\n---\nafter\n"));
+
+        assert!(
+            is_resource_limit(&output),
+            "{script}: {:?}",
+            output.diagnostics
+        );
+        assert_eq!(output.text, "after\n");
+    }
+}
+
+#[test]
+fn safe_engine_data_limits() {
+    let output = render_safe(indoc! {r#"
+        ---
+        let s = "spam";
+        loop { s += s; }
+        ---
+    "#});
+    assert!(is_resource_limit(&output), "{:?}", output.diagnostics);
+
+    let output = render_safe(indoc! {"
+        ---
+        let numbers = [];
+        loop { numbers.push(numbers.len()); }
+        ---
+    "});
+    assert!(is_resource_limit(&output), "{:?}", output.diagnostics);
+}
+
+#[test]
+fn safe_engine_host_functions() {
+    // each of these would allocate far more than the host has before rhai could check it
+    let inputs = [
+        indoc! {r#"
+            ---
+            import "rand" as rand;
+            let s = rand::rand_alpha_numeric(4611686018427387904);
+            ---
+        "#},
+        "@(\"x\" | pad_left(4611686018427387904))\n",
+        "@(1 | pad_right(4611686018427387904, 'x'))\n",
+        indoc! {r#"
+            ---
+            let item = "x";
+            item.pad(1000, 'x');
+            let items = [];
+            items.pad(10000, item);
+            ---
+            @(items | join)
+        "#},
+    ];
+
+    for input in inputs {
+        let result = template::render_with_engine(template::new_safe_engine(), input);
+        assert!(result.is_err(), "{input}");
+        assert!(is_resource_limit(&render_safe(input)), "{input}");
+    }
+}
+
+#[test]
+fn safe_engine_expressions() {
+    let output = render_safe(indoc! {"
+        ---
+        fn spin() { loop {} }
+        ---
+        before
+        @(spin())
+        after
+    "});
+
+    assert!(is_resource_limit(&output), "{:?}", output.diagnostics);
+    assert_eq!(output.text, "before\n\nafter\n");
+}
+
+#[test]
+fn safe_engine_total_operations() {
+    // every expression stays under the limit of a single evaluation,
+    // but not under the limit of the whole render, so the loop ends early
+    let output = render_safe(indoc! {"
+        ---
+        fn work() { let n = 0; while n < 100000 { n += 1; } n }
+        ---
+        @while true
+            @(work())
+        @end
+        after
+    "});
+
+    assert!(output.diagnostics.len() >= 2, "{:?}", output.diagnostics);
+    assert!(output
+        .diagnostics
+        .iter()
+        .all(|diagnostic| matches!(diagnostic.kind, template::DiagnosticKind::ResourceLimit(_))));
+    assert!(output.text.starts_with("100000\n100000\n"));
+    assert!(output.text.ends_with("after\n"));
+}
+
+#[test]
+fn safe_engine_disables_eval() {
+    let output = render_safe(indoc! {r#"
+        ---
+        eval("1 + 1");
+        ---
+    "#});
+
+    assert_eq!(output.diagnostics.len(), 1);
+    assert!(!is_resource_limit(&output));
+}
+
+#[test]
+fn trim_markers_join_lines() {
+    let input = indoc! {"
+        Answer:
+        @-if true
+        yes
+        @-else
+        no
+        @-end
+        , it is.
+    "};
+
+    assert_eq!(template::render(input).unwrap(), "Answer:yes, it is.\n");
+}
+
+#[test]
+fn trim_markers_suppress_blank_lines() {
+    let input = indoc! {"
+        # Units
+        @for unit in [\"m\", \"s\"] @-
+
+          - @unit
+        @end@-
+
+        Done
+    "};
+
+    assert_eq!(
+        template::render(input).unwrap(),
+        "# Units\n- m\n- s\nDone\n"
+    );
+}
+
+#[test]
+fn trim_markers_on_lines() {
+    let mut scope = rhai::Scope::new();
+    scope.push(
+        "xs",
+        vec![rhai::Dynamic::from(1_i64), 2_i64.into(), 3_i64.into()],
+    );
+
+    let input = indoc! {"
+        Numbers: @-
+        @for x in xs
+          @x@-
+        @-if !forloop.last
+          , @-
+        @end
+        @end
+        @-.
+    "};
+
+    let engine = template::new_engine();
+    let env = template::Environment::with_scope(engine, scope);
+    let output = template::render_with_environment(env, input).unwrap();
+
+    assert_eq!(output, "Numbers: 1, 2, 3.\n");
+}
+
+#[test]
+fn trim_markers_placement() {
+    // markers go at the start or end of directives, like on lines of text
+    let input = indoc! {"
+        a @-
+        @if true @-
+
+          b
+        @-end @-
+
+          c
+        @-let x = 1 @-
+        @-for i in [1, 2] @-
+          @i
+        @-end
+    "};
+
+    assert_eq!(template::render(input).unwrap(), "a bc12");
+}
+
+#[test]
+fn trim_markers_macro_call() {
+    let input = indoc! {"
+        @macro bold(text)
+        **@text**
+        @end
+        This is @-
+        @bold(\"important\") @-
+
+        , really.
+    "};
+
+    assert_eq!(
+        template::render(input).unwrap(),
+        "This is **important**\n, really.\n"
+    );
+}
+
+#[test]
+fn trim_markers_source_map() {
+    let input = indoc! {"
+        a
+        @-if true
+        b
+        @end
+        c @-
+        d
+        e
+    "};
+
+    let output = template::render_lenient(input);
+
+    assert_eq!(output.text, "ab\nc d\ne\n");
+    assert_eq!(output.source_map.len(), 3);
+    assert_eq!(output.source_map.template_line(1), Some(1));
+    assert_eq!(output.source_map.template_line(2), Some(5));
+    assert_eq!(output.source_map.template_line(3), Some(7));
+}
+
+#[test]
+fn inline_if() {
+    let mut scope = rhai::Scope::new();
+    scope.push("count", 1_i64);
+
+    test_render_with_scope(
+        scope,
+        "You have @count @if(count == 1){card}else{cards} left.@if(count > 5){ Hurry!}",
+        "You have 1 card left.",
+    );
+}
+
+#[test]
+fn inline_if_else_with_spaces() {
+    test_render(
+        "@if(false){no} else {yes, @(1 + 1) times} @if(true){@if(true){nested}}",
+        "yes, 2 times nested",
+    );
+}
+
+#[test]
+fn inline_for() {
+    let mut scope = rhai::Scope::new();
+    scope.push(
+        "xs",
+        vec![rhai::Dynamic::from(1_i64), 2_i64.into(), 3_i64.into()],
+    );
+    scope.push("empty", rhai::Array::new());
+
+    test_render_with_scope(
+        scope,
+        indoc! {"
+            Numbers: @for(x in xs){@x@if(!forloop.last){, }}.
+            Pairs: @for((i, [k, v]) in #{a: 1}){@i: @k=@v}
+            Nothing: @for(x in empty){@x}else{none}
+        "},
+        indoc! {"
+            Numbers: 1, 2, 3.
+            Pairs: 0: a=1
+            Nothing: none
+        "},
+    );
+}
+
+#[test]
+fn inline_blocks_with_braces() {
+    test_render(
+        r#"a @if(true){@("}")} end @for(x in ["{", "}"]){<@x>} @if(true){don't \} {ok}}"#,
+        r#"a } end <{><}> don't \} {ok}"#,
+    );
+}
+
+#[test]
+fn inline_blocks_in_directive_blocks() {
+    test_render(
+        indoc! {"
+            @for x in [1, 2]
+              - @x is @if(x % 2 == 0){even}else{odd}
+            @end
+        "},
+        indoc! {"
+            - 1 is odd
+            - 2 is even
+        "},
+    );
+}
+
+#[test]
+fn error_inline_blocks() {
+    test_diagnostics(
+        indoc! {"
+            a @if(true
+            b @if(true) c
+            c @if(true){d
+            d @for(x of xs){e}
+            e @for(1 in [1]){f}
+            f @if(1 +){g} h
+        "},
+        "a \nb \nc \nd \ne \nf  h",
+        &[(1, 6), (2, 12), (3, 12), (4, 8), (5, 8), (6, 10)],
+    );
+}
+
+#[test]
+fn line_comments() {
+    let input = indoc! {"
+        @# a note for authors
+        Hello, @(\"World\")! @# not rendered
+          @# indented
+        Bye
+    "};
+
+    assert_eq!(template::render(input).unwrap(), "Hello, World!\nBye\n");
+}
+
+#[test]
+fn block_comments() {
+    let input = indoc! {"
+        a @* inline *@b@* another *@ c
+        @*
+        @if true
+        commented out
+        @end
+        *@
+        d @* spanning
+        lines *@ e @(1 + 1)
+        @* whole line *@
+        f
+    "};
+
+    let output = template::render_lenient(input);
+
+    assert!(output.diagnostics.is_empty());
+    assert_eq!(output.text, "a b c\nd  e 2\nf\n");
+    assert_eq!(output.source_map.template_line(2), Some(7));
+    assert_eq!(output.source_map.template_line(3), Some(10));
+}
+
+#[test]
+fn directive_comments() {
+    let input = indoc! {r#"
+        @if true @# a note
+          @let x = "@# kept" @* another note *@
+          @x
+        @end
+        @for i in [1, 2] @-   @# trimmed
+          @i
+        @end @* done *@
+    "#};
+
+    assert_eq!(template::render(input).unwrap(), "\\@# kept\n1\n2\n");
+}
+
+#[test]
+fn error_comments() {
+    test_diagnostics(
+        indoc! {"
+            a @if(true){b @* c} d
+            e @* f
+            g
+        "},
+        "a b  d\ne",
+        &[(1, 15), (2, 3)],
+    );
+}
+
+fn test_render_injection(input: &str, expected: &str) {
+    let mut scope = rhai::Scope::new();
+    scope.push("emphasis", "*not bold*");
+    scope.push("script", "<script>alert(1)</script>");
+    scope.push("separator", "---");
+    scope.push("heading", "# not a heading");
+
+    test_render_with_scope(scope, input, expected);
+}
+
+#[test]
+fn escape_markdown() {
+    test_render_injection(
+        indoc! {"
+            Text: @emphasis @script
+            @separator
+              @heading
+            @(\"1. one\") and @(\"1. one\")
+        "},
+        indoc! {r"
+            Text: \*not bold\* \<script\>alert\(1\)\</script\>
+            \---
+              \# not a heading
+            1\. one and 1. one
+        "},
+    );
+}
+
+#[test]
+fn escape_link_destination() {
+    let mut scope = rhai::Scope::new();
+    scope.push("x", "x) ![img](javascript:alert(1)");
+
+    let engine = template::new_engine();
+    let env = template::Environment::with_scope(engine, scope);
+    let output = template::render_with_environment(env, "[click](@x)").unwrap();
+
+    assert_eq!(
+        output,
+        "[click](x\\) !\\[img\\]\\(javascript:alert\\(1\\))\n"
+    );
+
+    let deck = flashmark::Renderer::new().render_markdown(&output);
+    assert!(
+        !deck.cards[0].front.contains("<img"),
+        "{}",
+        deck.cards[0].front
+    );
+}
+
+#[test]
+fn escape_raw() {
+    test_render_injection(
+        "@!(emphasis) @raw(script) @!(\"<b>\" + \"</b>\")",
+        "*not bold* <script>alert(1)</script> <b></b>",
+    );
+}
+
+#[test]
+fn escape_context() {
+    test_render_injection(
+        indoc! {"
+            +++
+            id: @emphasis
+            +++
+            `@emphasis` and $`@emphasis`$ but @emphasis
+            ```
+            @script
+            ```
+            <div>
+            @script
+            </div>
+
+            @emphasis
+        "},
+        indoc! {r"
+            +++
+            id: *not bold*
+            +++
+            `*not bold*` and $`*not bold*`$ but \*not bold\*
+            ```
+            <script>alert(1)</script>
+            ```
+            <div>
+            &lt;script&gt;alert(1)&lt;/script&gt;
+            </div>
+
+            \*not bold\*
+        "},
+    );
+}
+
+#[test]
+fn escape_code_block_breakout() {
+    let mut scope = rhai::Scope::new();
+    scope.push("x", "```\n<script>alert(1)</script>\n---\nnew slide");
+
+    let engine = template::new_engine();
+    let env = template::Environment::with_scope(engine, scope);
+    let output = template::render_with_environment(env, "```\n@x\n```\n").unwrap();
+
+    assert_eq!(
+        output,
+        "```\n\u{200b}```\n<script>alert(1)</script>\n\u{200b}---\nnew slide\n```\n"
+    );
+    assert_eq!(flashmark::slides::Slides::new(&output).count(), 1);
+}
+
+#[test]
+fn escape_metadata_breakout() {
+    let mut scope = rhai::Scope::new();
+    scope.push("x", "a\n+++\n# injected");
+    scope.push("y", "+++");
+
+    let engine = template::new_engine();
+    let env = template::Environment::with_scope(engine, scope);
+    let input = "+++\ntitle: @x\n@y\n+++\nbody\n+++\n@y\n";
+    let output = template::render_with_environment(env, input).unwrap();
+
+    // only the first line of a slide opens a metadata block
+    assert_eq!(
+        output,
+        "+++\ntitle: a +++ # injected\n\u{200b}+++\n+++\nbody\n+++\n\\+++\n"
+    );
+
+    let slide = flashmark::slides::Slide::parse(&output);
+    assert_eq!(
+        slide.metadata,
+        Some("title: a +++ # injected\n\u{200b}+++\n")
+    );
+}
+
+#[test]
+fn escape_code_span() {
+    test_render_injection(
+        indoc! {r#"
+            \`@emphasis `@("a`" + emphasis)` ``@("a``" + emphasis + "`")``
+        "#},
+        "\\`\\*not bold\\* `a``*not bold*` ``a```*not bold*`\u{200b}``",
+    );
+}
+
+#[test]
+fn escape_disabled() {
+    let mut env = template::Environment::with_engine(template::new_engine());
+    env.set_autoescape(false);
+    env.scope_mut().push("emphasis", "*bold*");
+
+    let output = template::render_with_environment(env, "@emphasis").unwrap();
+    assert_eq!(output, "*bold*\n");
+}
+
+#[test]
+fn filters() {
+    let mut scope = rhai::Scope::new();
+    scope.push("name", "ada lovelace");
+    scope.push("price", 1234.5_f64);
+    scope.push("count", 2_i64);
+
+    test_render_with_scope(
+        scope,
+        indoc! {"
+            @(name | title) costs @(price | currency), @(name | upper | lower)
+            @(price | round(0) | thousands) for @count @(count | plural(\"child\", \"children\"))
+            @(price | currency(\"€\") | pad_left(12, '.'))
+        "},
+        indoc! {"
+            Ada Lovelace costs \\$1,234.50, ada lovelace
+            1,235 for 2 children
+            \\...€1,234.50
+        "},
+    );
+}
+
+#[test]
+fn filter_library() {
+    test_render(
+        indoc! {r#"
+            @(3.14159 | fixed(2)) @(0.125 | percent(1)) @(1234567 | thousands)
+            @(1 | ordinal) @(2 | ordinal) @(3 | ordinal) @(11 | ordinal) @(22 | ordinal)
+            @(1 | plural("card")) @(5 | plural("card"))
+            @([1, 2, 3] | join) @(["a", "b"] | join(" / "))
+            [@("x" | pad_right(3))] [@(7 | pad_left(3, '0'))]
+        "#},
+        indoc! {"
+            3.14 12.5% 1,234,567
+            1st 2nd 3rd 11th 22nd
+            card cards
+            1, 2, 3 a / b
+            [x  ] [007]
+        "},
+    );
+}
+
+#[test]
+fn filters_defined_in_front_matter() {
+    test_render(
+        indoc! {r#"
+            ---
+            fn shout(text) { text.to_upper() + "!" }
+            ---
+            @("hey" | shout) @(false || true) @(6 | 3) @(4 | 2 | plural("card"))
+        "#},
+        "HEY! true 7 cards",
+    );
+}
+
+#[test]
+fn filters_and_bitwise_or() {
+    use flashmark::template::{self, CompiledTemplate};
+
+    let mut scope = rhai::Scope::new();
+    scope.push("flags", 4_i64);
+
+    test_render_with_scope(
+        scope,
+        "@(1 | 2) @(flags | 1) @(flags | 1 | plural(\"flag\")) @([1 | 2] | join)\n",
+        "3 5 flags 3\n",
+    );
+
+    let template = CompiledTemplate::compile(
+        template::new_engine(),
+        indoc! {"
+            ---
+            fn twice(n) { n * 2 }
+            ---
+            @(n | 1 | twice)
+        "},
+    )
+    .unwrap();
+
+    let mut scope = rhai::Scope::new();
+    scope.push("n", 2_i64);
+    assert_eq!(template.render(scope).unwrap(), "6\n");
+}
+
+#[test]
+fn error_filters() {
+    test_diagnostics(
+        indoc! {r#"
+            a @(1 | round(2 +))
+            b @("x" | 1)
+            c @("x" | missing)
+        "#},
+        "a \nb \nc",
+        &[(1, 18), (2, 9), (3, 11)],
+    );
+}
+
+#[test]
+fn pad_filters_limit() {
+    let output = render_safe("[@(\"x\" | pad_left(3))] [@(1 | pad_right(3, '.'))]\n");
+    assert_eq!(output.text, "[  x] [1..]\n");
+
+    for filter in ["pad_left(4611686018427387904)", "pad_right(2000000, 'é')"] {
+        let output = render_safe(&format!("@(\"x\" | {filter})\n"));
+        assert!(is_resource_limit(&output), "{filter}");
+    }
+}
+
+#[test]
+fn formatted_values() {
+    test_render(
+        indoc! {r#"
+            ---
+            let primes = [2, 3, 5];
+            let point = #{ y: 2.5, x: 1.0 };
+            ---
+            @(0.1 + 0.2) @(10.0 / 4) @primes (@point) @(1e-12) @(1e-12 * 0.0)
+        "#},
+        "0.3 2.5 2, 3, 5 (x: 1, y: 2.5) 1e-12 0",
+    );
+}
+
+#[test]
+fn rationals() {
+    test_render(
+        indoc! {r#"
+            ---
+            let flour = rational(3, 4, "cup");
+            ---
+            @flour @(rational(6, 2, "cups")) @(flour.with_unit("")) @(flour.unit)
+            @(flour.numer + flour.denom) @(rational(2, -6)) @(flour.to_float())
+        "#},
+        "3/4 cup 3 cups 3/4 cup\n7 -1/3 0.75",
+    );
+}
+
+#[test]
+fn custom_formatter() {
+    #[derive(Clone)]
+    struct Celsius(f64);
+
+    let mut engine = template::new_engine();
+    engine.register_fn("celsius", Celsius);
+
+    let mut env = template::Environment::with_engine(engine);
+    let formatter = env.formatter_mut();
+    formatter.register(|value: &Celsius, _| format!("{} °C", value.0));
+    formatter.set_float_precision(Some(1));
+    formatter.set_mixed_fractions(true);
+
+    let output = template::render_with_environment(
+        env,
+        "@(celsius(21.5)) @(2.0 / 3) @(rational(7, 2, \"cup\"))",
+    )
+    .unwrap();
+    assert_eq!(output, "21.5 °C 0.7 3 1/2 cup\n");
+}
+
+#[test]
+fn error_rationals() {
+    test_diagnostics(
+        indoc! {r#"
+            a @(rational(1, 0))
+            b @(rational(-9223372036854775807 - 1, -1))
+            c @(rational(1, 2, "cup") + 1)
+        "#},
+        "a \nb \nc",
+        &[(1, 5), (2, 5), (3, 27)],
+    );
+}
+
+#[test]
+fn bare_expressions() {
+    test_render(
+        indoc! {r#"
+            ---
+            let item_count = 3;
+            let x2 = "two";
+            let user = #{ name: "Ada", tags: ["math", "code"] };
+            let card = #{ front: "What?" };
+            ---
+            @item_count items, @x2, @user.name, @user.tags[1] and @card.front.len()
+            Hello, @user.name. Is it @x2? (@item_count) @user.tags[0]s
+        "#},
+        indoc! {"
+            3 items, two, Ada, code and 5
+            Hello, Ada. Is it two? (3) maths
+        "},
+    );
+}
+
+#[test]
+fn error_bare_expressions() {
+    test_diagnostics(
+        indoc! {r#"
+            ---
+            let user = #{ name: "Ada" };
+            ---
+            a @user.missing_method() b
+            c @user.name[5] d
+        "#},
+        "a  b\nc  d",
+        &[(4, 9), (5, 14)],
+    );
+}
+
+#[test]
+fn expression_delimiters() {
+    test_render(
+        indoc! {r#"
+            ---
+            fn f(x) { x * 2 }
+            let a = 1;
+            let b = 2;
+            ---
+            @((a) + (b)) @("a)b") @(')') @(f(f(a)) + 1) @(`(${ "}" })`) @(a /* ) */ + b)
+            @([a, b][1]) @(#{ x: "(" }.x) @(b)) @(a)(b)
+        "#},
+        "3 a\\)b \\) 5 \\(}\\) 3\n2 \\( 2) 1(b)",
+    );
+}
+
+#[test]
+fn error_unterminated_expressions() {
+    test_diagnostics(
+        indoc! {r#"
+            a @(1 + (2)
+            b @("c)
+            c @(x /* ) d
+            d @(f(x]) e
+            e @if(x == ")"){yes} @(`${ 1 )`)
+        "#},
+        "a \nb \nc \nd \ne",
+        &[(1, 4), (2, 5), (3, 7), (4, 6), (5, 25), (5, 7)],
+    );
+}